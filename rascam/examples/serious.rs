@@ -34,6 +34,7 @@ fn serious(info: &CameraInfo) {
         iso: ISO_AUTO,
         zero_copy: true,
         use_encoder: false,
+        annotation: None,
     };
 
     camera.set_camera_format(&settings).unwrap();
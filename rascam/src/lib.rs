@@ -369,6 +369,37 @@ impl SeriousCamera {
         }
     }
 
+    /// Sets the text stamped onto captured images. `None` disables the annotation.
+    pub fn set_annotation(&mut self, annotation: Option<&Annotation>) -> Result<(), CameraError> {
+        unsafe {
+            let mut cfg: ffi::MMAL_PARAMETER_CAMERA_ANNOTATE_V3_T = mem::zeroed();
+            cfg.hdr.id = ffi::MMAL_PARAMETER_ANNOTATE as u32;
+            cfg.hdr.size = mem::size_of::<ffi::MMAL_PARAMETER_CAMERA_ANNOTATE_V3_T>() as u32;
+
+            if let Some(annotation) = annotation {
+                cfg.enable = 1;
+                cfg.enable_text_background = annotation.background as i32;
+                cfg.text_size = annotation.text_size;
+
+                // Leave room for the nul terminator
+                let max_len = cfg.text.len() - 1;
+                let mut len = annotation.text.len().min(max_len);
+                while !annotation.text.is_char_boundary(len) {
+                    len -= 1;
+                }
+                for (dst, src) in cfg.text.iter_mut().zip(annotation.text[..len].bytes()) {
+                    *dst = src as c_char;
+                }
+            }
+
+            let status = ffi::mmal_port_parameter_set(self.camera.as_ref().control, &cfg.hdr);
+            match status {
+                MMAL_STATUS_T::MMAL_SUCCESS => Ok(()),
+                s => Err(MmalError::with_status("Unable to set annotation".to_owned(), s).into()),
+            }
+        }
+    }
+
     pub fn set_camera_params(&mut self, info: &CameraInfo) -> Result<(), CameraError> {
         unsafe {
             let mut cfg: ffi::MMAL_PARAMETER_CAMERA_CONFIG_T = mem::zeroed();
@@ -1173,7 +1204,8 @@ impl SimpleCamera {
         let settings = self.settings.as_ref().unwrap();
         let camera = &mut self.serious;
 
-        camera.set_camera_num(0)?;
+        // The camera info port id is the index MMAL expects when selecting a camera.
+        camera.set_camera_num(self.info.port_id as u8)?;
         camera.create_encoder()?;
         camera.set_camera_params(&self.info)?;
 
@@ -1182,6 +1214,9 @@ impl SimpleCamera {
         // camera.set_camera_format(ffi::MMAL_ENCODING_JPEG, self.info.max_width, self.info.max_height, false)?;
         camera.set_camera_awb()?;
         camera.set_camera_format(settings)?;
+        if settings.annotation.is_some() {
+            camera.set_annotation(settings.annotation.as_ref())?;
+        }
         camera.enable_control_port(false)?;
 
         camera.enable()?;
@@ -1196,6 +1231,13 @@ impl SimpleCamera {
         Ok(())
     }
 
+    /// Sets the text stamped onto the following captures. `None` disables the annotation.
+    ///
+    /// The camera must be activated first.
+    pub fn set_annotation(&mut self, annotation: Option<&Annotation>) -> Result<(), CameraError> {
+        self.serious.set_annotation(annotation)
+    }
+
     /// Captures a single image from the camera synchronously and writes it to the given `Write` trait.
     ///
     /// If there is an error
@@ -46,6 +46,8 @@ pub struct CameraSettings {
     pub zero_copy: bool,
     /// `use_encoder` will go away
     pub use_encoder: bool,
+    /// Text drawn onto captured images. `None` leaves images unannotated.
+    pub annotation: Option<Annotation>,
 }
 
 impl Default for CameraSettings {
@@ -57,6 +59,33 @@ impl Default for CameraSettings {
             iso: ISO_AUTO,
             zero_copy: false,
             use_encoder: true,
+            annotation: None,
         }
     }
 }
+
+/// Text the camera firmware stamps onto captured images.
+///
+/// Text longer than 255 bytes is truncated.
+///
+/// ```
+/// # use rascam::{Annotation, SimpleCamera};
+/// #
+/// # let info = rascam::info().unwrap().cameras[0].clone();
+/// # let mut camera = SimpleCamera::new(info).unwrap();
+/// # camera.activate().unwrap();
+/// #
+/// let annotation = Annotation {
+///     text: "Front door".to_owned(),
+///     ..Annotation::default()
+/// };
+/// camera.set_annotation(Some(&annotation)).unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Annotation {
+    pub text: String,
+    /// Size of the text. 0 uses the firmware default.
+    pub text_size: u8,
+    /// Draw the text on a solid background
+    pub background: bool,
+}
@@ -0,0 +1,238 @@
+use crate::config::{AnalogChannel, AnalogGain, Distance, PresenceSensor};
+use crate::distance::{DistanceSensor, ThresholdsRx};
+use crate::state::{Action, ActionRx, Event, EventTx};
+use crate::utils;
+
+use ads1015_adc::*;
+use futures::{
+    future::FutureExt, // for `.fuse()`
+    select,
+};
+use log::{debug, error, info, warn};
+
+use rppal::gpio::{Gpio, Trigger};
+use rppal::i2c::I2c;
+use tokio::sync::watch;
+use tokio::task;
+use tokio::time::{delay_for, delay_until, Duration, Instant};
+
+/// Give up on a conversion if the alert pin hasn't fired after this long
+const CONVERSION_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug)]
+enum Conversion {
+    Ready,
+    NotReady,
+}
+
+/// A converted reading from one of the analog channels
+#[derive(Clone, Debug)]
+pub struct Reading {
+    pub name: String,
+    /// Raw ADC counts
+    pub counts: u16,
+    /// Counts after the channel's conversion
+    pub value: f32,
+    pub unit: String,
+}
+
+/// What to do with the readings from a channel
+enum Sensor {
+    Distance(DistanceSensor),
+    Named(AnalogChannel),
+}
+
+/// A channel taking its turn on the ADC
+struct Scheduled {
+    pin: Pin,
+    gain: AnalogGain,
+    interval: Duration,
+    next_due: Instant,
+    sensor: Sensor,
+}
+
+impl Scheduled {
+    fn new(channel: u8, gain: AnalogGain, interval: u64, sensor: Sensor) -> Option<Self> {
+        match channel_pin(channel) {
+            Some(pin) => Some(Scheduled {
+                pin,
+                gain,
+                interval: Duration::from_millis(interval),
+                next_due: Instant::now(),
+                sensor,
+            }),
+            None => {
+                error!("Invalid ADC channel {}", channel);
+                None
+            }
+        }
+    }
+
+    fn reading(&mut self, counts: u16, event_tx: &mut EventTx) {
+        match &mut self.sensor {
+            Sensor::Distance(distance) => distance.reading(counts, event_tx),
+            Sensor::Named(channel) => {
+                let reading = Reading {
+                    name: channel.name.clone(),
+                    counts,
+                    value: counts as f32 * channel.scale + channel.offset,
+                    unit: channel.unit.clone(),
+                };
+                debug!("Analog reading {:?}", reading);
+                if let Err(err) = event_tx.send(Event::AnalogReading(reading)) {
+                    error!("Error sending event: {}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Sample the Sharp IR distance sensor, if it is used, and the other analog channels on the ADC.
+/// There is one alert pin so channels are read one at a time, taking turns
+/// when more than one is due.
+pub fn create_analog_task(
+    mut rx: ActionRx,
+    distance_config: Distance,
+    thresholds: ThresholdsRx,
+    analog_config: Vec<AnalogChannel>,
+    mut event_tx: EventTx,
+) -> task::JoinHandle<()> {
+    task::spawn_local(async move {
+        let alert_pin = distance_config.alert_pin;
+        let mut channels = vec![];
+        if distance_config.sensor == PresenceSensor::SharpIr {
+            channels.extend(Scheduled::new(
+                distance_config.channel,
+                distance_config.gain,
+                distance_config.interval,
+                Sensor::Distance(DistanceSensor::new(distance_config, thresholds)),
+            ));
+        }
+        for channel in analog_config {
+            channels.extend(Scheduled::new(
+                channel.channel,
+                channel.gain,
+                channel.interval,
+                Sensor::Named(channel),
+            ));
+        }
+        if channels.is_empty() {
+            debug!("No analog channels to read");
+            return;
+        }
+
+        let i2c = I2c::new().expect("Unable to open I2C bus.");
+        let mut adc = ADS1015::new(i2c).unwrap();
+        let gpios = Gpio::new().unwrap();
+
+        //adc.data_rate = SampleRate::Rate920;
+
+        adc.set_alert_status().unwrap();
+
+        let (send_conversion_ready, mut recieve_conversion_ready) =
+            watch::channel(Conversion::NotReady);
+
+        let pin = gpios
+            .get(alert_pin)
+            .unwrap()
+            .into_input_pulldown();
+
+        let pin_watcher = utils::watch_pin(pin, Trigger::RisingEdge, rx.clone(), move |x| {
+            info!("Pin triggered: {:?}", x);
+            if let Err(x) = send_conversion_ready.broadcast(Conversion::Ready) {
+                error!("Error broadcasting pin conversion ready: {:?}", x);
+            }
+        });
+
+        // Channel waiting on a conversion
+        let mut converting: Option<usize> = None;
+        let mut last = 0;
+        let mut delay = delay_until(next_due(&channels)).fuse();
+        let mut pin_watcher = pin_watcher.fuse();
+        loop {
+            select! {
+                // Just to run the pin watcher. It will quit on it's own.
+                _ = pin_watcher => {},
+                // Read the value when the conversion pin triggers
+                _conversion_event = recieve_conversion_ready.recv().fuse() => {
+                    if let Some(index) = converting.take() {
+                        let value = adc.read_conversion().unwrap();
+                        let channel = &mut channels[index];
+                        channel.next_due = Instant::now() + channel.interval;
+                        channel.reading(value, &mut event_tx);
+                        delay = delay_until(next_due(&channels)).fuse();
+                    }
+                }
+                // Request the next channel due be read async.
+                _ = delay => {
+                    if let Some(index) = converting.take() {
+                        warn!("Conversion of channel {} timed out", index);
+                        channels[index].next_due = Instant::now() + channels[index].interval;
+                    }
+                    match due_channel(&channels, last, Instant::now()) {
+                        Some(index) => {
+                            let channel = &channels[index];
+                            adc.gain = gain(channel.gain);
+                            adc.request_read(channel.pin).unwrap();
+                            converting = Some(index);
+                            last = index;
+                            delay = delay_for(CONVERSION_TIMEOUT).fuse();
+                        }
+                        None => delay = delay_until(next_due(&channels)).fuse(),
+                    }
+                }
+                action = rx.recv().fuse() => {
+                    match action {
+                       Some(Action::Shutdown) => {
+                            debug!("Shut down analog task");
+                            break;
+                        }
+                        Some(Action::Startup) => debug!("Analog Task in startup mode"),
+                        _ => {
+                         // Nothing
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// When the next channel is due to be read
+fn next_due(channels: &[Scheduled]) -> Instant {
+    channels
+        .iter()
+        .map(|channel| channel.next_due)
+        .min()
+        .unwrap_or_else(Instant::now)
+}
+
+/// The first channel due after the last one read, so a busy channel can't
+/// starve the others.
+fn due_channel(channels: &[Scheduled], last: usize, now: Instant) -> Option<usize> {
+    (1..=channels.len())
+        .map(|offset| (last + offset) % channels.len())
+        .find(|index| channels[*index].next_due <= now)
+}
+
+fn gain(gain: AnalogGain) -> Gain {
+    match gain {
+        AnalogGain::TwoThirds => Gain::Gain2_3,
+        AnalogGain::One => Gain::Gain1,
+        AnalogGain::Two => Gain::Gain2,
+        AnalogGain::Four => Gain::Gain4,
+        AnalogGain::Eight => Gain::Gain8,
+        AnalogGain::Sixteen => Gain::Gain16,
+    }
+}
+
+/// Map an ADC channel number to its pin
+fn channel_pin(channel: u8) -> Option<Pin> {
+    match channel {
+        0 => Some(Pin::P0),
+        1 => Some(Pin::P1),
+        2 => Some(Pin::P2),
+        3 => Some(Pin::P3),
+        _ => None,
+    }
+}
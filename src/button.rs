@@ -0,0 +1,163 @@
+use crate::config::{Button, ButtonAction};
+use crate::state::{Action, ActionRx, Event, EventTx};
+use crate::utils;
+
+use std::time;
+
+use futures::{
+    future::FutureExt, // for `.fuse()`
+    select,
+};
+use log::{debug, error, info};
+use rppal::gpio::{Gpio, Level, Trigger};
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio::time::{Duration, Instant};
+
+/// The ways the button can be pressed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gesture {
+    Short,
+    Long,
+    Double,
+}
+
+/// Turns debounced button edges into gestures
+struct GestureRecognizer {
+    debounce: Duration,
+    long_press: Duration,
+    double_press: Duration,
+    last_edge: Option<Instant>,
+    pressed_at: Option<Instant>,
+    /// Release time of a short press that might become a double press
+    pending_short: Option<Instant>,
+}
+
+impl GestureRecognizer {
+    fn new(button_config: &Button) -> Self {
+        GestureRecognizer {
+            debounce: Duration::from_millis(button_config.debounce),
+            long_press: Duration::from_millis(button_config.long_press),
+            double_press: Duration::from_millis(button_config.double_press),
+            last_edge: None,
+            pressed_at: None,
+            pending_short: None,
+        }
+    }
+
+    /// Handle an edge from the button pin
+    fn edge(&mut self, level: Level, now: Instant) -> Option<Gesture> {
+        if let Some(last) = self.last_edge {
+            if now - last < self.debounce {
+                return None;
+            }
+        }
+        self.last_edge = Some(now);
+
+        match level {
+            Level::High => {
+                if self.pressed_at.is_none() {
+                    self.pressed_at = Some(now);
+                }
+                None
+            }
+            Level::Low => {
+                let pressed_at = self.pressed_at.take()?;
+                if now - pressed_at >= self.long_press {
+                    self.pending_short = None;
+                    Some(Gesture::Long)
+                } else if self.pending_short.take().is_some() {
+                    Some(Gesture::Double)
+                } else {
+                    self.pending_short = Some(now);
+                    None
+                }
+            }
+        }
+    }
+
+    /// When a pending short press is no longer a possible double press
+    fn deadline(&self) -> Option<Instant> {
+        match self.pressed_at {
+            Some(_) => None,
+            None => self.pending_short.map(|released| released + self.double_press),
+        }
+    }
+
+    fn timeout(&mut self, now: Instant) -> Option<Gesture> {
+        match self.deadline() {
+            Some(deadline) if deadline <= now => {
+                self.pending_short = None;
+                Some(Gesture::Short)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Watch the button and send the event mapped to each gesture
+pub fn create_button_task(
+    mut rx: ActionRx,
+    button_config: Button,
+    event_tx: EventTx,
+) -> task::JoinHandle<()> {
+    task::spawn(async move {
+        let button = match Gpio::new().and_then(|gpios| gpios.get(button_config.pin)) {
+            Ok(pin) => pin.into_input_pulldown(),
+            Err(err) => {
+                error!("Unable to open button pin {}: {}", button_config.pin, err);
+                return;
+            }
+        };
+
+        let (edge_tx, mut edge_rx) = mpsc::unbounded_channel::<(Level, time::Instant)>();
+        let _button_watcher = utils::watch_pin(button, Trigger::Both, rx.clone(), move |level| {
+            let _ = edge_tx.send((level, time::Instant::now()));
+        });
+
+        let mut recognizer = GestureRecognizer::new(&button_config);
+        loop {
+            let gesture = select! {
+                edge = edge_rx.recv().fuse() => match edge {
+                    Some((level, at)) => recognizer.edge(level, Instant::from_std(at)),
+                    None => None,
+                },
+                _ = utils::wait_until(recognizer.deadline()).fuse() => {
+                    recognizer.timeout(Instant::now())
+                }
+                action = rx.recv().fuse() => {
+                    if let Some(Action::Shutdown) = action {
+                        debug!("Shutting down button task");
+                        break;
+                    }
+                    None
+                }
+            };
+
+            if let Some(gesture) = gesture {
+                info!("Button gesture {:?}", gesture);
+                let actions = &button_config.actions;
+                let action = match gesture {
+                    Gesture::Short => actions.short_press,
+                    Gesture::Long => actions.long_press,
+                    Gesture::Double => actions.double_press,
+                };
+                if let Some(event) = gesture_event(action) {
+                    if let Err(err) = event_tx.send(event) {
+                        error!("Error sending button event: {}", err)
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn gesture_event(action: ButtonAction) -> Option<Event> {
+    match action {
+        ButtonAction::Click => Some(Event::IncClick),
+        ButtonAction::TakePicture => Some(Event::TakeImageRequest(None)),
+        ButtonAction::Enroll => Some(Event::StartEnrollment),
+        ButtonAction::Maintenance => Some(Event::ToggleMaintenance),
+        ButtonAction::Nothing => None,
+    }
+}
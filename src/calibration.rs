@@ -0,0 +1,79 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// A distance sensor reading taken with a target at a known distance
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct CalibrationPoint {
+    /// Raw ADC counts
+    pub counts: u16,
+    /// Measured distance in centimeters
+    pub cm: f32,
+}
+
+/// Maps ADC counts to centimeters. IR distance sensors aren't linear so this
+/// interpolates between the points of a calibration table.
+#[derive(Clone)]
+pub struct Calibration {
+    // Sorted by counts
+    points: Vec<CalibrationPoint>,
+}
+
+impl Calibration {
+    pub fn new(mut points: Vec<CalibrationPoint>) -> Self {
+        points.sort_by_key(|point| point.counts);
+        Calibration { points }
+    }
+
+    pub fn points(&self) -> &[CalibrationPoint] {
+        &self.points
+    }
+
+    /// Distance in centimeters for the counts. None without at least two points.
+    /// Readings outside the table are clamped to its ends.
+    pub fn to_cm(&self, counts: u16) -> Option<f32> {
+        if self.points.len() < 2 {
+            return None;
+        }
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if counts <= first.counts {
+            return Some(first.cm);
+        }
+        if counts >= last.counts {
+            return Some(last.cm);
+        }
+        self.points.windows(2).find_map(|pair| {
+            let (low, high) = (pair[0], pair[1]);
+            if counts >= low.counts && counts <= high.counts {
+                let fraction =
+                    (counts - low.counts) as f32 / (high.counts - low.counts).max(1) as f32;
+                Some(low.cm + fraction * (high.cm - low.cm))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Counts read at the distance. None if the distance is outside the table.
+    pub fn to_counts(&self, cm: f32) -> Option<u16> {
+        self.points.windows(2).find_map(|pair| {
+            let (low, high) = (pair[0], pair[1]);
+            let (near, far) = if low.cm <= high.cm {
+                (low.cm, high.cm)
+            } else {
+                (high.cm, low.cm)
+            };
+            if cm >= near && cm <= far {
+                let fraction = if high.cm == low.cm {
+                    0.0
+                } else {
+                    (cm - low.cm) / (high.cm - low.cm)
+                };
+                let counts =
+                    low.counts as f32 + fraction * (high.counts as f32 - low.counts as f32);
+                Some(counts.round() as u16)
+            } else {
+                None
+            }
+        })
+    }
+}
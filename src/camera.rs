@@ -1,57 +1,77 @@
-use crate::state::{Action, ActionRx, Event, EventTx};
+use crate::config::{self, Images};
+use crate::illuminator::Illuminator;
+use crate::state::{Action, ActionRx, Event, EventTx, State};
 
+use std::sync::Arc;
 use std::time::Duration;
 
 use log::{debug, error, warn};
 //use rascam;
 
+use tokio::sync::Mutex;
 use tokio::task;
 use tokio::time::delay_for;
 
-pub fn create_picture_task(mut rx: ActionRx, event_tx: EventTx) -> task::JoinHandle<()> {
+pub fn create_picture_task(
+    mut rx: ActionRx,
+    images_config: Images,
+    illuminator_config: Option<config::Illuminator>,
+    state: Arc<Mutex<State>>,
+    event_tx: EventTx,
+) -> task::JoinHandle<()> {
     task::spawn_local(async move {
         debug!("Starting picture task");
-        let mut camera;
-        let camera_info = match rascam::info() {
-            Ok(info) => {
-                if info.cameras.len() < 1 {
-                    warn!("No cameras found on device");
-                    None
-                } else {
-                    if let Err(err) = event_tx.send(Event::HasCamera(true)) {
-                        error!("Error sending click event: {}", err)
-                    }
-                    debug!("We have a camera");
-                    camera = rascam::SimpleCamera::new(info.cameras[0].clone()).unwrap();
-                    let settings = rascam::CameraSettings {
-                        width: 1920,
-                        height: 1080,
-                        ..rascam::CameraSettings::default()
-                    };
-                    camera.configure(settings);
-                    camera.activate().unwrap();
-                    delay_for(Duration::from_millis(2000)).await;
-                    Some(camera)
-                }
-            }
+        let mut cameras = match rascam::info() {
+            Ok(info) => open_cameras(&info, &images_config),
             Err(err) => {
                 error!("Error opening camera: {}", err);
-                None
+                vec![]
             }
         };
-        if let Some(mut camera) = camera_info {
+        if cameras.is_empty() {
+            warn!("No cameras found on device");
+        } else {
+            let indexes = cameras.iter().map(|(index, _)| *index).collect();
+            if let Err(err) = event_tx.send(Event::HasCameras(indexes)) {
+                error!("Error sending camera event: {}", err)
+            }
+            let mut illuminator = illuminator_config.and_then(Illuminator::new);
+            delay_for(Duration::from_millis(2000)).await;
             loop {
                 match rx.recv().await {
-                    Some(Action::TakePicture) => {
+                    Some(Action::TakePicture(selected)) => {
                         debug!("Request for a picture");
-                        let picture = camera.take_one_async().await;
-                        match picture {
-                            Ok(pict) => {
-                                if let Err(err) = event_tx.send(Event::AddImage(pict)) {
-                                    error!("Error saving picture: {}", err)
+                        if let Some(illuminator) = illuminator.as_mut() {
+                            let light_level =
+                                illuminator.light_level(&state.lock().await.analog_readings);
+                            illuminator.light_up(light_level).await;
+                        }
+                        let selected_cameras = cameras
+                            .iter_mut()
+                            .filter(|(index, _)| selected.map_or(true, |s| s == *index));
+                        for (index, camera) in selected_cameras {
+                            if images_config.annotate {
+                                let annotation = annotation(&*state.lock().await);
+                                if let Err(err) = camera.set_annotation(Some(&annotation)) {
+                                    error!("Error annotating picture: {}", err);
                                 }
                             }
-                            Err(err) => error!("Error taking picture: {}", err),
+                            let picture = camera.take_one_async().await;
+                            match picture {
+                                Ok(pict) => {
+                                    if let Err(err) = event_tx.send(Event::AddImage(*index, pict))
+                                    {
+                                        error!("Error saving picture: {}", err)
+                                    }
+                                }
+                                Err(err) => error!("Error taking picture: {}", err),
+                            }
+                        }
+                        if let Some(illuminator) = illuminator.as_mut() {
+                            illuminator.switch_off();
+                        }
+                        if let Err(err) = event_tx.send(Event::CaptureComplete) {
+                            error!("Error sending capture complete: {}", err)
                         }
                     }
                     Some(Action::Shutdown) => break,
@@ -62,3 +82,53 @@ pub fn create_picture_task(mut rx: ActionRx, event_tx: EventTx) -> task::JoinHan
         debug!("Ending picture task");
     })
 }
+
+/// Open and activate each configured camera that is attached to the device.
+fn open_cameras(info: &rascam::Info, images_config: &Images) -> Vec<(u8, rascam::SimpleCamera)> {
+    let mut cameras = vec![];
+    for index in &images_config.cameras {
+        let camera_info = match info.cameras.get(*index as usize) {
+            Some(camera_info) => camera_info,
+            None => {
+                warn!("Camera {} not found on device", index);
+                continue;
+            }
+        };
+        debug!("We have camera {}: {}", index, camera_info);
+        let mut camera = match rascam::SimpleCamera::new(camera_info.clone()) {
+            Ok(camera) => camera,
+            Err(err) => {
+                error!("Error creating camera {}: {}", index, err);
+                continue;
+            }
+        };
+        let settings = rascam::CameraSettings {
+            width: images_config.width as u32,
+            height: images_config.height as u32,
+            ..rascam::CameraSettings::default()
+        };
+        camera.configure(settings);
+        match camera.activate() {
+            Ok(()) => cameras.push((*index, camera)),
+            Err(err) => error!("Error activating camera {}: {}", index, err),
+        }
+    }
+    cameras
+}
+
+/// Build the text stamped onto a picture from the current state.
+fn annotation(state: &State) -> rascam::Annotation {
+    let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+    let cat = match state.last_tag_read() {
+        Some(tag) => match state.cat_name(&tag) {
+            Some(name) => name.to_owned(),
+            None => format!("Tag {}", tag),
+        },
+        None => "Unknown cat".to_owned(),
+    };
+    rascam::Annotation {
+        text: format!("{}  {}  Distance: {}", time, cat, state.distance),
+        background: true,
+        ..rascam::Annotation::default()
+    }
+}
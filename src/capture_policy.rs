@@ -0,0 +1,82 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::config::Captures;
+
+/// Decides if an automatic capture should happen, so a cat pacing in front of
+/// the sensor doesn't fill the image buffer.
+pub struct CapturePolicy {
+    config: Captures,
+    last_capture: Option<Instant>,
+    visit_captures: u32,
+    // Pictures were taken on entering this visit
+    entry_captured: bool,
+    // Times of the captures in the last hour
+    recent_captures: VecDeque<Instant>,
+}
+
+/// What triggered the capture
+#[derive(Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Enter,
+    Exit,
+}
+
+impl CapturePolicy {
+    pub fn new(config: Captures) -> Self {
+        CapturePolicy {
+            config,
+            last_capture: None,
+            visit_captures: 0,
+            entry_captured: false,
+            recent_captures: VecDeque::new(),
+        }
+    }
+
+    /// A new visit has started. Resets the per visit count.
+    pub fn start_visit(&mut self) {
+        self.visit_captures = 0;
+        self.entry_captured = false;
+    }
+
+    /// Check the policy and record the capture if it is allowed
+    pub fn try_capture(&mut self, trigger: Trigger, now: Instant) -> bool {
+        let hour = Duration::from_secs(60 * 60);
+        while let Some(time) = self.recent_captures.front() {
+            if now.duration_since(*time) >= hour {
+                let _ = self.recent_captures.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if trigger == Trigger::Exit && !self.config.capture_on_exit {
+            return false;
+        }
+        // The exit pictures pair with the entry ones, however short the visit
+        let paired = trigger == Trigger::Exit && self.entry_captured;
+        if let Some(last) = self.last_capture.filter(|_| !paired) {
+            if now.duration_since(last) < Duration::from_secs(self.config.min_interval) {
+                return false;
+            }
+        }
+        if let Some(max) = self.config.max_per_visit {
+            if self.visit_captures >= max {
+                return false;
+            }
+        }
+        if let Some(max) = self.config.max_per_hour {
+            if self.recent_captures.len() >= max as usize {
+                return false;
+            }
+        }
+
+        self.last_capture = Some(now);
+        self.visit_captures += 1;
+        if trigger == Trigger::Enter {
+            self.entry_captured = true;
+        }
+        self.recent_captures.push_back(now);
+        true
+    }
+}
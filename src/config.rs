@@ -1,8 +1,12 @@
+use std::collections::HashMap;
+
 use serde_derive::{Deserialize, Serialize};
 //use tokio::prelude::*;
 //use tokio::fs::{File, read};
 use toml;
 
+use crate::calibration::CalibrationPoint;
+
 #[derive(Deserialize, Serialize)]
 pub struct Config {
     pub title: String,
@@ -10,25 +14,502 @@ pub struct Config {
     pub images: Images,
     pub rfid: Rfid,
     pub distance: Distance,
+    pub illuminator: Option<Illuminator>,
+    #[serde(default)]
+    pub captures: Captures,
+    #[serde(default)]
+    pub button: Button,
+    /// Extra ADC channels sampled alongside the distance sensor
+    #[serde(default)]
+    pub analog: Vec<AnalogChannel>,
+    pub hopper: Option<Hopper>,
+    pub notify: Option<Notify>,
+    pub load_cell: Option<LoadCell>,
+    pub lid: Option<Lid>,
+    #[serde(default)]
+    pub feeder: Feeder,
 }
 #[derive(Deserialize, Serialize)]
 pub struct Images {
     pub count: usize,
-    width: u16,
-    height: u16,
+    pub width: u16,
+    pub height: u16,
+    /// Index of each camera to capture from
+    #[serde(default = "default_cameras")]
+    pub cameras: Vec<u8>,
+    /// Stamp the time, cat and distance onto each image
+    #[serde(default)]
+    pub annotate: bool,
+}
+
+fn default_cameras() -> Vec<u8> {
+    vec![0]
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct Rfid {
     pub valid_ids: Vec<String>,
+    #[serde(default)]
+    pub reader: ReaderModel,
+    /// Serial device the reader is attached to
+    #[serde(default = "default_device")]
+    pub device: String,
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+    #[serde(default)]
+    pub parity: Parity,
+    /// Time in milli seconds without a read before a tag has left
+    #[serde(default = "default_absence_timeout")]
+    pub absence_timeout: u64,
+    /// Data pins of a Wiegand reader
+    pub wiegand: Option<Wiegand>,
+    /// Time in seconds enrollment mode waits for a tag
+    #[serde(default = "default_enrollment_timeout")]
+    pub enrollment_timeout: u64,
+    /// GPIO pin of an LED lit during enrollment mode
+    pub enrollment_led: Option<u8>,
+    /// Names of the cats keyed by tag id
+    #[serde(default)]
+    pub names: HashMap<String, String>,
+}
+
+fn default_device() -> String {
+    "/dev/ttyS0".to_string()
+}
+
+fn default_baud_rate() -> u32 {
+    9600
+}
+
+fn default_absence_timeout() -> u64 {
+    2000
+}
+
+fn default_enrollment_timeout() -> u64 {
+    30
+}
+
+/// Parity of the RFID reader serial port
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+impl Default for Parity {
+    fn default() -> Self {
+        Parity::None
+    }
+}
+
+/// The kind of tag the RFID reader reads
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReaderModel {
+    /// 125 kHz EM4100 readers such as the RDM6300
+    Em4100,
+    /// 134.2 kHz FDX-B pet microchip readers such as the WL-134
+    FdxB,
+    /// Wiegand-26 or Wiegand-34 readers on two GPIO pins
+    Wiegand,
 }
 
 #[derive(Deserialize, Serialize)]
+pub struct Wiegand {
+    /// GPIO pin of the D0 data line
+    pub d0_pin: u8,
+    /// GPIO pin of the D1 data line
+    pub d1_pin: u8,
+    /// Time in milli seconds without a bit before the frame is complete
+    #[serde(default = "default_frame_timeout")]
+    pub frame_timeout: u64,
+}
+
+fn default_frame_timeout() -> u64 {
+    25
+}
+
+impl Default for ReaderModel {
+    fn default() -> Self {
+        ReaderModel::Em4100
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Distance {
-    pub enter_threshold: u16,
-    pub exit_threshold: u16,
+    /// What detects the cat
+    #[serde(default)]
+    pub sensor: PresenceSensor,
+    /// Enter threshold in the sensor's units. Needed unless given in centimeters
+    pub enter_threshold: Option<u16>,
+    /// Exit threshold in the sensor's units. Needed unless given in centimeters
+    pub exit_threshold: Option<u16>,
+    /// Enter threshold in centimeters. Replaces the counts, and has to be within
+    /// the calibration for the Sharp IR sensor
+    pub enter_threshold_cm: Option<f32>,
+    /// Exit threshold in centimeters. Replaces the counts, and has to be within
+    /// the calibration for the Sharp IR sensor
+    pub exit_threshold_cm: Option<f32>,
+    /// Readings taken at known distances. Replaced by a saved calibration
+    #[serde(default)]
+    pub calibration: Vec<CalibrationPoint>,
+    /// GPIO pin the ADC uses to alert when a conversion is done
+    #[serde(default = "default_alert_pin")]
     pub alert_pin: u8,
     pub interval: u64,
+    /// ADC channel of the distance sensor
+    #[serde(default)]
+    pub channel: u8,
+    #[serde(default = "default_distance_gain")]
+    pub gain: AnalogGain,
+    /// Number of readings to take the median of. 1 turns the median off
+    #[serde(default = "default_median_window")]
+    pub median_window: usize,
+    /// Weight of the newest reading in the moving average, above 0 and at
+    /// most 1. None turns it off
+    pub ema_alpha: Option<f32>,
+    /// Time in milli seconds above the enter threshold before entering
+    #[serde(default)]
+    pub enter_dwell: u64,
+    /// Time in milli seconds below the exit threshold before exiting
+    #[serde(default)]
+    pub exit_dwell: u64,
+    pub ultrasonic: Option<Ultrasonic>,
+    pub pir: Option<Pir>,
+}
+
+fn default_alert_pin() -> u8 {
+    17
+}
+
+/// The sensor that detects the cat
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PresenceSensor {
+    /// Sharp IR distance sensor on the ADC. Readings are ADC counts that rise
+    /// as the cat gets closer.
+    SharpIr,
+    /// HC-SR04 ultrasonic module. Readings are milli meters.
+    Ultrasonic,
+    /// PIR motion sensor. Readings are 1 with motion and 0 without.
+    Pir,
+}
+
+impl Default for PresenceSensor {
+    fn default() -> Self {
+        PresenceSensor::SharpIr
+    }
+}
+
+/// Pins of an HC-SR04 ultrasonic module
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Ultrasonic {
+    /// GPIO pin of the trigger input
+    pub trigger_pin: u8,
+    /// GPIO pin of the echo output
+    pub echo_pin: u8,
+}
+
+/// Pin of a PIR motion sensor
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Pir {
+    /// GPIO pin of the sensor output
+    pub pin: u8,
+}
+
+fn default_median_window() -> usize {
+    1
+}
+
+fn default_distance_gain() -> AnalogGain {
+    AnalogGain::Two
+}
+
+/// A sensor on one of the ADC channels
+#[derive(Deserialize, Serialize)]
+pub struct AnalogChannel {
+    /// Name the readings are reported under
+    pub name: String,
+    /// ADC channel, 0 to 3
+    pub channel: u8,
+    #[serde(default)]
+    pub gain: AnalogGain,
+    /// Time between readings in milli seconds
+    pub interval: u64,
+    /// Readings are converted to `counts * scale + offset`
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub offset: f32,
+    /// Unit of the converted value
+    #[serde(default)]
+    pub unit: String,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// Food level sensor in the hopper
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Hopper {
+    /// Name of the analog channel measuring the hopper
+    pub channel: String,
+    /// Converted reading when the hopper is empty
+    pub empty: f32,
+    /// Converted reading when the hopper is full
+    pub full: f32,
+    /// Percent full below which the food is low
+    #[serde(default = "default_low_threshold")]
+    pub low_threshold: f32,
+}
+
+fn default_low_threshold() -> f32 {
+    20.0
+}
+
+/// Where notifications such as the food running low are sent
+#[derive(Deserialize, Serialize)]
+pub struct Notify {
+    /// Program run with the message as its argument, eg a script sending an email
+    pub command: String,
+}
+
+/// HX711 load cell under the bowl
+#[derive(Deserialize, Serialize)]
+pub struct LoadCell {
+    /// GPIO pin of the clock line
+    pub clock_pin: u8,
+    /// GPIO pin of the data line
+    pub data_pin: u8,
+    /// Time between weighings in milli seconds
+    #[serde(default = "default_weigh_interval")]
+    pub interval: u64,
+    /// Readings averaged into each weighing
+    #[serde(default = "default_weigh_samples")]
+    pub samples: usize,
+    /// Raw reading with an empty bowl. Replaced by a saved tare
+    #[serde(default)]
+    pub offset: f32,
+    /// Raw counts per gram. Replaced by a saved calibration
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+fn default_weigh_interval() -> u64 {
+    1000
+}
+
+fn default_weigh_samples() -> usize {
+    5
+}
+
+/// Tare and calibration of the load cell
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct ScaleCalibration {
+    pub offset: f32,
+    pub scale: f32,
+}
+
+/// Servo lid over the bowl that opens for the cats in `rfid.valid_ids`
+#[derive(Deserialize, Serialize)]
+pub struct Lid {
+    /// Hardware PWM channel. 0 is on GPIO 18 and 1 on GPIO 19
+    pub pwm_channel: u8,
+    /// Servo pulse width in micro seconds with the lid open
+    #[serde(default = "default_open_pulse")]
+    pub open_pulse: u64,
+    /// Servo pulse width in micro seconds with the lid closed
+    #[serde(default = "default_closed_pulse")]
+    pub closed_pulse: u64,
+    /// Time in milli seconds the lid stays open after the tag leaves
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u64,
+    /// Readings at or nearer than this mean something is under the lid. In the
+    /// distance sensor's units
+    pub pinch_threshold: u16,
+    /// Times the lid backs off a step for something under it before it waits
+    /// where it is
+    #[serde(default = "default_pinch_retries")]
+    pub pinch_retries: u32,
+}
+
+fn default_open_pulse() -> u64 {
+    2000
+}
+
+fn default_closed_pulse() -> u64 {
+    1000
+}
+
+fn default_grace_period() -> u64 {
+    5000
+}
+
+fn default_pinch_retries() -> u32 {
+    3
+}
+
+/// Programmable gain of the ADC, named by the amplification
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnalogGain {
+    TwoThirds,
+    One,
+    Two,
+    Four,
+    Eight,
+    Sixteen,
+}
+
+impl Default for AnalogGain {
+    fn default() -> Self {
+        AnalogGain::One
+    }
+}
+
+/// IR LED or light switched on while taking pictures
+#[derive(Deserialize, Serialize)]
+pub struct Illuminator {
+    /// GPIO pin driving the light
+    pub pin: u8,
+    /// Time in milli seconds to light up before taking a picture
+    pub lead_time: u64,
+    /// Hour of the day the night starts
+    pub night_start: Option<u8>,
+    /// Hour of the day the night ends
+    pub night_end: Option<u8>,
+    /// Name of the analog channel with a light sensor
+    pub light_channel: Option<String>,
+    /// Converted light sensor readings below this are dark
+    pub light_threshold: Option<f32>,
+}
+
+/// Limits on automatic captures
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Captures {
+    /// Minimum time in seconds between captures. Doesn't apply to the exit
+    /// capture of a visit that had an entry capture.
+    #[serde(default)]
+    pub min_interval: u64,
+    /// Maximum captures from entering until the next entry
+    pub max_per_visit: Option<u32>,
+    /// Maximum captures in any hour
+    pub max_per_hour: Option<u32>,
+    /// Take a picture when leaving the distance threshold
+    #[serde(default = "default_true")]
+    pub capture_on_exit: bool,
+}
+
+impl Default for Captures {
+    fn default() -> Self {
+        Captures {
+            min_interval: 0,
+            max_per_visit: None,
+            max_per_hour: None,
+            capture_on_exit: true,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Settings of the feeder state machine
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Feeder {
+    /// Time in seconds after a cat leaves before the feeder is idle
+    pub cooldown: u64,
+    /// Time in seconds before maintenance mode ends on its own
+    pub maintenance_timeout: u64,
+}
+
+impl Default for Feeder {
+    fn default() -> Self {
+        Feeder {
+            cooldown: 60,
+            maintenance_timeout: 30 * 60,
+        }
+    }
+}
+
+/// The push button and what its gestures do. Anything left out takes its default.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Button {
+    /// GPIO pin of the button
+    pub pin: u8,
+    /// Time in milli seconds to ignore edges after an edge
+    pub debounce: u64,
+    /// Time in milli seconds the button is held for a long press
+    pub long_press: u64,
+    /// Time in milli seconds between presses of a double press
+    pub double_press: u64,
+    pub actions: ButtonActions,
+}
+
+impl Default for Button {
+    fn default() -> Self {
+        Button {
+            pin: 20,
+            debounce: 30,
+            long_press: 2000,
+            double_press: 400,
+            actions: ButtonActions::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct ButtonActions {
+    pub short_press: ButtonAction,
+    pub long_press: ButtonAction,
+    pub double_press: ButtonAction,
+}
+
+impl Default for ButtonActions {
+    fn default() -> Self {
+        ButtonActions {
+            short_press: ButtonAction::Click,
+            long_press: ButtonAction::Enroll,
+            double_press: ButtonAction::TakePicture,
+        }
+    }
+}
+
+/// What a button gesture does
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ButtonAction {
+    /// Increment the click count
+    Click,
+    /// Take a picture with all the cameras
+    TakePicture,
+    /// Enter enrollment mode
+    Enroll,
+    /// Start maintenance mode, or end it if it's on
+    Maintenance,
+    Nothing,
+}
+
+/// File the enrolled cats are saved to. Kept apart from the config file so
+/// saving doesn't lose its comments.
+const REGISTRY_FILE: &str = "cats.toml";
+
+/// File written when the scale is tared or calibrated
+const SCALE_FILE: &str = "scale.toml";
+
+/// File written by the guided distance calibration
+const CALIBRATION_FILE: &str = "calibration.toml";
+
+#[derive(Deserialize, Serialize)]
+struct CalibrationFile {
+    points: Vec<CalibrationPoint>,
 }
 
 pub fn read_config() -> Config {
@@ -38,3 +519,52 @@ pub fn read_config() -> Config {
 
     config.expect("Error parsing config file")
 }
+
+/// Names of the enrolled cats keyed by tag id. Empty if nothing has been enrolled.
+pub fn read_registry() -> HashMap<String, String> {
+    match std::fs::read_to_string(REGISTRY_FILE) {
+        Ok(lines) => toml::from_str(&lines).expect("Error parsing registry file"),
+        Err(_err) => HashMap::new(),
+    }
+}
+
+pub fn save_registry(names: &HashMap<String, String>) -> std::io::Result<()> {
+    let lines = toml::to_string(names)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+    std::fs::write(REGISTRY_FILE, lines)
+}
+
+/// Calibration saved by the guided calibration mode, if there is one.
+pub fn read_calibration() -> Option<Vec<CalibrationPoint>> {
+    match std::fs::read_to_string(CALIBRATION_FILE) {
+        Ok(lines) => {
+            let file: CalibrationFile =
+                toml::from_str(&lines).expect("Error parsing calibration file");
+            Some(file.points)
+        }
+        Err(_err) => None,
+    }
+}
+
+pub fn save_calibration(points: &[CalibrationPoint]) -> std::io::Result<()> {
+    let file = CalibrationFile {
+        points: points.to_vec(),
+    };
+    let lines = toml::to_string(&file)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+    std::fs::write(CALIBRATION_FILE, lines)
+}
+
+/// Tare and calibration saved from the web page, if there is one.
+pub fn read_scale() -> Option<ScaleCalibration> {
+    match std::fs::read_to_string(SCALE_FILE) {
+        Ok(lines) => Some(toml::from_str(&lines).expect("Error parsing scale file")),
+        Err(_err) => None,
+    }
+}
+
+pub fn save_scale(calibration: &ScaleCalibration) -> std::io::Result<()> {
+    let lines = toml::to_string(calibration)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+    std::fs::write(SCALE_FILE, lines)
+}
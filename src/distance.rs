@@ -1,114 +1,228 @@
-use crate::config::Distance;
-use crate::state::{Action, ActionRx, Event, EventTx};
-use crate::utils;
-
-use ads1015_adc::*;
-use futures::{
-    future::FutureExt, // for `.fuse()`
-    select,
-};
-use log::{debug, error, info};
-
-use rppal::gpio::{Gpio, Trigger};
-use rppal::i2c::I2c;
+use crate::calibration::Calibration;
+use crate::config::{Distance, PresenceSensor};
+use crate::state::{ActionRx, Event, EventTx};
+
+mod filter;
+mod pir;
+mod ultrasonic;
+
+use filter::Filter;
+
+use log::{error, info};
+
 use tokio::sync::watch;
 use tokio::task;
-use tokio::time::{delay_for, Duration};
+use tokio::time::{Duration, Instant};
+
+/// Shorthand for the send half of the thresholds channel.
+pub type ThresholdsTx = watch::Sender<Thresholds>;
+
+/// Shorthand for the recieve half of the thresholds channel.
+pub type ThresholdsRx = watch::Receiver<Thresholds>;
 
+/// Where a cat enters and leaves, in the sensor's units
 #[derive(Clone, Copy, Debug)]
-enum Conversion {
-    Ready,
-    NotReady,
+pub struct Thresholds {
+    pub enter: u16,
+    pub exit: u16,
 }
 
-pub fn create_distance_task(
-    mut rx: ActionRx,
-    distance_config: Distance,
-    mut event_tx: EventTx,
-) -> task::JoinHandle<()> {
-    task::spawn_local(async move {
-        let i2c = I2c::new().expect("Unable to open I2C bus.");
-        let mut adc = ADS1015::new(i2c).unwrap();
-        let gpios = Gpio::new().unwrap();
-
-        adc.gain = Gain::Gain2;
-        //adc.data_rate = SampleRate::Rate920;
-
-        adc.set_alert_status().unwrap();
-
-        let (send_conversion_ready, mut recieve_conversion_ready) =
-            watch::channel(Conversion::NotReady);
-
-        let pin = gpios
-            .get(distance_config.alert_pin)
-            .unwrap()
-            .into_input_pulldown();
-
-        let pin_watcher = utils::watch_pin(pin, Trigger::RisingEdge, rx.clone(), move |x| {
-            info!("Pin triggered: {:?}", x);
-            if let Err(x) = send_conversion_ready.broadcast(Conversion::Ready) {
-                error!("Error broadcasting pin conversion ready: {:?}", x);
-            }
-        });
-
-        let mut delay = delay_for(Duration::from_millis(distance_config.interval)).fuse();
-
-        let mut in_threshold: bool = false;
-        let mut pin_watcher = pin_watcher.fuse();
-        loop {
-            select! {
-                // Just to run the pin watcher. It will quit on it's own.
-                _ = pin_watcher => {},
-                // Request when the conversion pin triggers
-                _conversion_event = recieve_conversion_ready.recv().fuse() => {
-                    let value = adc.read_conversion().unwrap();
-                    evaluate_value(value, &distance_config, &mut event_tx, &mut in_threshold);
-                    // Reset the delay for when to trigger the pin again
-                    delay = delay_for(Duration::from_millis(distance_config.interval)).fuse();
-                }
-                // Request the pin be read async.
-                _ = delay => {
-                    adc.request_read(Pin::P0).unwrap();
-                }
-                action = rx.recv().fuse() => {
-                    match action {
-                       Some(Action::Shutdown) => {
-                            debug!("Shut down distance task");
-                            break;
-                        }
-                        Some(Action::Startup) => debug!("Distance Task in startup mode"),
-                        _ => {
-                         // Nothing
-                        }
-                    }
-                }
-            }
+/// Where the reading is in relation to the thresholds
+#[derive(Default)]
+struct Threshold {
+    inside: bool,
+    // When the readings first crossed the threshold waiting for the dwell time
+    crossing_since: Option<Instant>,
+}
+
+/// The distance sensor on the ADC. Filters the readings and decides when a
+/// cat enters or leaves.
+pub struct DistanceSensor {
+    config: Distance,
+    /// Change when a new calibration is saved
+    thresholds: ThresholdsRx,
+    filter: Filter,
+    threshold: Threshold,
+}
+
+impl DistanceSensor {
+    pub fn new(config: Distance, thresholds: ThresholdsRx) -> Self {
+        DistanceSensor {
+            filter: Filter::new(&config),
+            thresholds,
+            threshold: Threshold::default(),
+            config,
         }
-    })
+    }
+
+    /// Handle a reading from the ADC
+    pub fn reading(&mut self, counts: u16, event_tx: &mut EventTx) {
+        let value = self.filter.apply(counts);
+        let thresholds = *self.thresholds.borrow();
+        evaluate_value(
+            value,
+            &self.config,
+            thresholds,
+            event_tx,
+            &mut self.threshold,
+        );
+    }
 }
 
-// Decide what to do with the value read from the ADC given the config. Possibly send
-// and event.
+// Decide what to do with the filtered value given the config. Possibly send
+// and event. A threshold only counts as crossed once the readings stay past it
+// for the dwell time.
 fn evaluate_value(
     value: u16,
     distance_config: &Distance,
+    thresholds: Thresholds,
     event_tx: &mut EventTx,
-    in_threshold: &mut bool,
+    threshold: &mut Threshold,
 ) {
     info!("Distance value: {}", value);
-    if value >= distance_config.enter_threshold && *in_threshold == false {
-        *in_threshold = true;
-        if let Err(err) = event_tx.send(Event::EnterDistanceThreshold(value)) {
-            error!("Error sending event: {}", err);
-        }
-    } else if value < distance_config.exit_threshold && *in_threshold == true {
-        *in_threshold = false;
-        if let Err(err) = event_tx.send(Event::ExitDistanceThreshold(value)) {
-            error!("Error sending event: {}", err);
+    let sensor = distance_config.sensor;
+    let (crossing, dwell) = if threshold.inside {
+        (
+            !is_within(sensor, value, thresholds.exit),
+            distance_config.exit_dwell,
+        )
+    } else {
+        (
+            is_within(sensor, value, thresholds.enter),
+            distance_config.enter_dwell,
+        )
+    };
+
+    let event = if crossing {
+        let now = Instant::now();
+        let since = *threshold.crossing_since.get_or_insert(now);
+        if now.duration_since(since) >= Duration::from_millis(dwell) {
+            threshold.inside = !threshold.inside;
+            threshold.crossing_since = None;
+            if threshold.inside {
+                Event::EnterDistanceThreshold(value)
+            } else {
+                Event::ExitDistanceThreshold(value)
+            }
+        } else {
+            Event::Distance(value)
         }
     } else {
-        if let Err(err) = event_tx.send(Event::Distance(value)) {
-            error!("Error sending event: {}", err);
+        threshold.crossing_since = None;
+        Event::Distance(value)
+    };
+
+    if let Err(err) = event_tx.send(event) {
+        error!("Error sending event: {}", err);
+    }
+}
+
+/// Is the reading at or nearer than the threshold. Ultrasonic readings get
+/// smaller as the cat gets closer, the others get larger.
+pub fn is_within(sensor: PresenceSensor, value: u16, threshold: u16) -> bool {
+    match sensor {
+        PresenceSensor::Ultrasonic => value <= threshold,
+        PresenceSensor::SharpIr | PresenceSensor::Pir => value >= threshold,
+    }
+}
+
+/// Start the task reading a presence sensor on the GPIO pins. The Sharp IR
+/// sensor is read by the analog task instead.
+pub fn create_distance_task(
+    rx: ActionRx,
+    distance_config: Distance,
+    thresholds: ThresholdsRx,
+    event_tx: EventTx,
+) -> Option<task::JoinHandle<()>> {
+    match distance_config.sensor {
+        PresenceSensor::SharpIr => None,
+        PresenceSensor::Ultrasonic => Some(ultrasonic::create_ultrasonic_task(
+            rx,
+            distance_config,
+            thresholds,
+            event_tx,
+        )),
+        PresenceSensor::Pir => Some(pir::create_pir_task(
+            rx,
+            distance_config,
+            thresholds,
+            event_tx,
+        )),
+    }
+}
+
+/// Work out the thresholds in the sensor's units. Thresholds given in
+/// centimeters replace the ones in counts. It's an error if one can't be
+/// converted with the calibration, or isn't given at all.
+pub fn thresholds(
+    distance_config: &Distance,
+    calibration: &Calibration,
+) -> Result<Thresholds, String> {
+    // Any motion is a cat
+    if distance_config.sensor == PresenceSensor::Pir {
+        return Ok(Thresholds { enter: 1, exit: 1 });
+    }
+    Ok(Thresholds {
+        enter: threshold(
+            "enter",
+            distance_config.enter_threshold,
+            distance_config.enter_threshold_cm,
+            distance_config.sensor,
+            calibration,
+        )?,
+        exit: threshold(
+            "exit",
+            distance_config.exit_threshold,
+            distance_config.exit_threshold_cm,
+            distance_config.sensor,
+            calibration,
+        )?,
+    })
+}
+
+fn threshold(
+    name: &str,
+    counts: Option<u16>,
+    cm: Option<f32>,
+    sensor: PresenceSensor,
+    calibration: &Calibration,
+) -> Result<u16, String> {
+    let cm = match (cm, counts) {
+        (Some(cm), _) => cm,
+        (None, Some(counts)) => return Ok(counts),
+        (None, None) => {
+            return Err(format!(
+                "The distance needs an {0}_threshold or {0}_threshold_cm",
+                name
+            ))
+        }
+    };
+    let counts = match sensor {
+        // Ultrasonic readings are in milli meters
+        PresenceSensor::Ultrasonic => Some((cm * 10.0).round() as u16),
+        _ => calibration.to_counts(cm),
+    };
+    match counts {
+        Some(counts) => {
+            info!("The {} threshold of {} cm is {}", name, cm, counts);
+            Ok(counts)
         }
+        None => Err(format!(
+            "The {} threshold of {} cm is outside the distance calibration",
+            name, cm
+        )),
+    }
+}
+
+/// Check the filter settings. An `ema_alpha` of 0 would hold the average at the
+/// first reading and one above 1 overshoots, so either is an error.
+pub fn check_filter(distance_config: &Distance) -> Result<(), String> {
+    match distance_config.ema_alpha {
+        Some(alpha) if alpha > 0.0 && alpha <= 1.0 => Ok(()),
+        Some(alpha) => Err(format!(
+            "The distance ema_alpha of {} needs to be above 0 and at most 1",
+            alpha
+        )),
+        None => Ok(()),
     }
 }
@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+
+use crate::config::Distance;
+
+/// Smooths raw ADC readings so a single spike doesn't look like a cat.
+/// Applies a median of the last readings, then an exponential moving average.
+/// Either stage can be turned off in the config.
+pub struct Filter {
+    median_window: usize,
+    recent: VecDeque<u16>,
+    ema_alpha: Option<f32>,
+    average: Option<f32>,
+}
+
+impl Filter {
+    pub fn new(distance_config: &Distance) -> Self {
+        Filter {
+            median_window: distance_config.median_window.max(1),
+            recent: VecDeque::new(),
+            ema_alpha: distance_config.ema_alpha,
+            average: None,
+        }
+    }
+
+    /// Add a reading and return the filtered value
+    pub fn apply(&mut self, value: u16) -> u16 {
+        if self.recent.len() >= self.median_window {
+            let _ = self.recent.pop_front();
+        }
+        self.recent.push_back(value);
+
+        let mut sorted = self.recent.iter().copied().collect::<Vec<u16>>();
+        sorted.sort_unstable();
+        let median = sorted[sorted.len() / 2];
+
+        match self.ema_alpha {
+            Some(alpha) => {
+                let average = match self.average {
+                    Some(average) => alpha * median as f32 + (1.0 - alpha) * average,
+                    None => median as f32,
+                };
+                self.average = Some(average);
+                average.round() as u16
+            }
+            None => median,
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use futures::{
+    future::FutureExt, // for `.fuse()`
+    select,
+};
+use log::{debug, error};
+use rppal::gpio::Gpio;
+use tokio::task;
+use tokio::time::{delay_for, Duration};
+
+use super::{DistanceSensor, ThresholdsRx};
+use crate::config::Distance;
+use crate::state::{Action, ActionRx, EventTx};
+
+/// Sample a PIR motion sensor. Motion reads as 1 and its absence as 0, so the
+/// dwell times decide how long the cat has to be still before it has left.
+pub fn create_pir_task(
+    mut rx: ActionRx,
+    distance_config: Distance,
+    thresholds: ThresholdsRx,
+    mut event_tx: EventTx,
+) -> task::JoinHandle<()> {
+    task::spawn_local(async move {
+        let pin = match &distance_config.pir {
+            Some(pir) => pir.pin,
+            None => {
+                error!("PIR sensor selected without a [distance.pir] pin");
+                return;
+            }
+        };
+        let pin = match Gpio::new().and_then(|gpios| gpios.get(pin)) {
+            Ok(pin) => pin.into_input_pulldown(),
+            Err(err) => {
+                error!("Unable to open PIR pin {}: {}", pin, err);
+                return;
+            }
+        };
+
+        let interval = Duration::from_millis(distance_config.interval);
+        let mut sensor = DistanceSensor::new(distance_config, thresholds);
+        loop {
+            select! {
+                _ = delay_for(interval).fuse() => {
+                    let motion = if pin.is_high() { 1 } else { 0 };
+                    sensor.reading(motion, &mut event_tx);
+                }
+                action = rx.recv().fuse() => if let Some(Action::Shutdown) = action {
+                    debug!("Shut down PIR task");
+                    break;
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,112 @@
+// An HC-SR04 starts a measurement on a 10µs pulse of the trigger pin. The echo
+// pin is then held high for as long as the sound takes to come back.
+use std::time;
+
+use futures::{
+    future::FutureExt, // for `.fuse()`
+    select,
+};
+use log::{debug, error};
+use rppal::gpio::{Gpio, InputPin, OutputPin};
+use tokio::task;
+use tokio::time::{delay_for, Duration};
+
+use super::{DistanceSensor, ThresholdsRx};
+use crate::config::Distance;
+use crate::state::{Action, ActionRx, EventTx};
+
+/// Reported when no echo comes back, which is as far as the module can see
+const MAX_RANGE_MM: u16 = 4000;
+
+/// Longest the module takes to start or finish an echo
+const ECHO_TIMEOUT: time::Duration = time::Duration::from_millis(30);
+
+pub fn create_ultrasonic_task(
+    mut rx: ActionRx,
+    distance_config: Distance,
+    thresholds: ThresholdsRx,
+    mut event_tx: EventTx,
+) -> task::JoinHandle<()> {
+    task::spawn_local(async move {
+        let pins = match &distance_config.ultrasonic {
+            Some(ultrasonic) => open_pins(ultrasonic.trigger_pin, ultrasonic.echo_pin),
+            None => {
+                error!("Ultrasonic sensor selected without [distance.ultrasonic] pins");
+                return;
+            }
+        };
+        let (mut trigger, mut echo) = match pins {
+            Some(pins) => pins,
+            None => return,
+        };
+
+        let interval = Duration::from_millis(distance_config.interval);
+        let mut sensor = DistanceSensor::new(distance_config, thresholds);
+        loop {
+            select! {
+                _ = delay_for(interval).fuse() => {
+                    // Timing the echo is a busy wait, so keep it off the event loop
+                    let measured = task::spawn_blocking(move || {
+                        let distance = measure(&mut trigger, &echo);
+                        (trigger, echo, distance)
+                    })
+                    .await;
+                    match measured {
+                        Ok((returned_trigger, returned_echo, distance)) => {
+                            trigger = returned_trigger;
+                            echo = returned_echo;
+                            sensor.reading(distance, &mut event_tx);
+                        }
+                        Err(err) => {
+                            error!("Error measuring distance: {}", err);
+                            break;
+                        }
+                    }
+                }
+                action = rx.recv().fuse() => if let Some(Action::Shutdown) = action {
+                    debug!("Shut down ultrasonic task");
+                    break;
+                }
+            }
+        }
+    })
+}
+
+fn open_pins(trigger_pin: u8, echo_pin: u8) -> Option<(OutputPin, InputPin)> {
+    let pins = Gpio::new().and_then(|gpios| {
+        let trigger = gpios.get(trigger_pin)?.into_output();
+        let echo = gpios.get(echo_pin)?.into_input();
+        Ok((trigger, echo))
+    });
+    match pins {
+        Ok(pins) => Some(pins),
+        Err(err) => {
+            error!("Unable to open ultrasonic pins: {}", err);
+            None
+        }
+    }
+}
+
+/// Distance to the nearest object in milli meters
+fn measure(trigger: &mut OutputPin, echo: &InputPin) -> u16 {
+    trigger.set_high();
+    std::thread::sleep(time::Duration::from_micros(10));
+    trigger.set_low();
+
+    let start = time::Instant::now();
+    while echo.is_low() {
+        if start.elapsed() > ECHO_TIMEOUT {
+            return MAX_RANGE_MM;
+        }
+    }
+    let rise = time::Instant::now();
+    while echo.is_high() {
+        if rise.elapsed() > ECHO_TIMEOUT {
+            return MAX_RANGE_MM;
+        }
+    }
+
+    // Sound travels 0.343 mm per µs and has to go there and back
+    let micros = rise.elapsed().as_micros() as f32;
+    ((micros * 0.1715) as u16).min(MAX_RANGE_MM)
+}
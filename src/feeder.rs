@@ -0,0 +1,491 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use log::info;
+
+use crate::config;
+
+/// Number of transitions to remember
+const MAX_TRANSITIONS: usize = 20;
+
+/// What the feeder is doing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeederState {
+    /// Waiting for a cat
+    Idle,
+    /// A cat is within the distance threshold
+    CatPresent,
+    /// Food is being dispensed. Nothing dispenses yet.
+    #[allow(dead_code)]
+    Dispensing,
+    /// The cat has left. Waits for the cooldown before going idle.
+    Cooldown,
+    /// Hardware failed. What depends on it is held back until it recovers or
+    /// the fault is cleared.
+    Fault,
+    /// Being refilled or cleaned. Automatic captures and notifications are
+    /// suspended until it ends or expires.
+    Maintenance,
+}
+
+impl fmt::Display for FeederState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FeederState::Idle => "Idle",
+            FeederState::CatPresent => "Cat present",
+            FeederState::Dispensing => "Dispensing",
+            FeederState::Cooldown => "Cooldown",
+            FeederState::Fault => "Fault",
+            FeederState::Maintenance => "Maintenance",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Hardware that can fail
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Device {
+    RfidReader,
+    LoadCell,
+}
+
+/// Something that can move the feeder to another state
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    CatArrived,
+    CatLeft,
+    CooldownOver,
+    Fault,
+    ClearFault,
+    StartMaintenance,
+    EndMaintenance,
+}
+
+/// The state the input moves to, or None if the input doesn't apply in the
+/// current state.
+pub fn transition(state: FeederState, input: Input) -> Option<FeederState> {
+    use FeederState::*;
+
+    match (state, input) {
+        (Maintenance, Input::EndMaintenance) => Some(Idle),
+        (Maintenance, _) => None,
+        (_, Input::StartMaintenance) => Some(Maintenance),
+        (Fault, Input::ClearFault) => Some(Idle),
+        (Fault, _) => None,
+        (_, Input::Fault) => Some(Fault),
+        (Idle, Input::CatArrived) | (Cooldown, Input::CatArrived) => Some(CatPresent),
+        (CatPresent, Input::CatLeft) | (Dispensing, Input::CatLeft) => Some(Cooldown),
+        (Cooldown, Input::CooldownOver) => Some(Idle),
+        _ => None,
+    }
+}
+
+/// A change of state
+pub struct Transition {
+    pub from: FeederState,
+    pub to: FeederState,
+    pub input: Input,
+    pub at: DateTime<Local>,
+}
+
+/// The feeder state machine along with the recent transitions
+pub struct Feeder {
+    state: FeederState,
+    since: Instant,
+    cooldown: Duration,
+    maintenance_timeout: Duration,
+    /// Oldest first
+    pub transitions: VecDeque<Transition>,
+    /// Why each device failed. The feeder is in the fault state while there
+    /// are any, or will be once maintenance ends.
+    pub faults: BTreeMap<Device, String>,
+    /// A cat is within the distance threshold, whatever the state
+    cat_present: bool,
+}
+
+impl Feeder {
+    pub fn new(config: &config::Feeder) -> Self {
+        Feeder {
+            state: FeederState::Idle,
+            since: Instant::now(),
+            cooldown: Duration::from_secs(config.cooldown),
+            maintenance_timeout: Duration::from_secs(config.maintenance_timeout),
+            transitions: VecDeque::new(),
+            faults: BTreeMap::new(),
+            cat_present: false,
+        }
+    }
+
+    pub fn state(&self) -> FeederState {
+        self.state
+    }
+
+    /// Apply the input. Returns true when the state changed.
+    pub fn handle(&mut self, input: Input) -> bool {
+        let to = match transition(self.state, input) {
+            Some(to) if to != self.state => to,
+            _ => return false,
+        };
+        info!("Feeder {} -> {} on {:?}", self.state, to, input);
+        if self.transitions.len() >= MAX_TRANSITIONS {
+            let _ = self.transitions.pop_front();
+        }
+        self.transitions.push_back(Transition {
+            from: self.state,
+            to,
+            input,
+            at: Local::now(),
+        });
+        self.state = to;
+        self.since = Instant::now();
+        true
+    }
+
+    /// A cat came within the distance threshold
+    pub fn cat_arrived(&mut self) {
+        self.cat_present = true;
+        let _ = self.handle(Input::CatArrived);
+    }
+
+    /// The cat left the distance threshold
+    pub fn cat_left(&mut self) {
+        self.cat_present = false;
+        let _ = self.handle(Input::CatLeft);
+    }
+
+    pub fn cat_present(&self) -> bool {
+        self.cat_present
+    }
+
+    /// Record the device failing. During maintenance the fault state waits
+    /// until maintenance ends.
+    pub fn fault(&mut self, device: Device, reason: String) {
+        let _ = self.faults.entry(device).or_insert(reason);
+        let _ = self.handle(Input::Fault);
+    }
+
+    /// The device is working again. The fault clears once nothing else has
+    /// failed.
+    pub fn recovered(&mut self, device: Device) {
+        if self.faults.remove(&device).is_some()
+            && self.faults.is_empty()
+            && self.handle(Input::ClearFault)
+        {
+            self.resume();
+        }
+    }
+
+    /// Clear every fault, including ones waiting for maintenance to end
+    pub fn clear_fault(&mut self) {
+        self.faults.clear();
+        if self.handle(Input::ClearFault) {
+            self.resume();
+        }
+    }
+
+    /// The device has failed and hasn't recovered or been cleared
+    pub fn has_fault(&self, device: Device) -> bool {
+        self.faults.contains_key(&device)
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.state == FeederState::Maintenance
+    }
+
+    /// Time until maintenance mode expires
+    pub fn maintenance_remaining(&self, now: Instant) -> Option<Duration> {
+        if self.in_maintenance() {
+            let elapsed = now.duration_since(self.since);
+            let remaining = self.maintenance_timeout.checked_sub(elapsed);
+            Some(remaining.unwrap_or_default())
+        } else {
+            None
+        }
+    }
+
+    /// Start maintenance mode. A fault stays until it's cleared, or comes back
+    /// when maintenance ends.
+    pub fn start_maintenance(&mut self) {
+        let _ = self.handle(Input::StartMaintenance);
+    }
+
+    /// End maintenance mode. Returns true if it was on.
+    pub fn end_maintenance(&mut self) -> bool {
+        if self.handle(Input::EndMaintenance) {
+            self.resume();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Pick up again after a fault or maintenance. A fault that was set or
+    // raised during maintenance comes back, or else a cat at the bowl is present.
+    fn resume(&mut self) {
+        if !self.faults.is_empty() {
+            let _ = self.handle(Input::Fault);
+        } else if self.cat_present {
+            let _ = self.handle(Input::CatArrived);
+        }
+    }
+
+    /// Move on from states that time out. Returns the input that was applied.
+    pub fn tick(&mut self, now: Instant) -> Option<Input> {
+        let elapsed = now.duration_since(self.since);
+        let input = match self.state {
+            FeederState::Cooldown if elapsed >= self.cooldown => Input::CooldownOver,
+            FeederState::Maintenance if elapsed >= self.maintenance_timeout => {
+                Input::EndMaintenance
+            }
+            _ => return None,
+        };
+        if !self.handle(input) {
+            return None;
+        }
+        if input == Input::EndMaintenance {
+            self.resume();
+        }
+        Some(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feeder() -> Feeder {
+        Feeder::new(&config::Feeder::default())
+    }
+
+    #[test]
+    fn test_transition() {
+        use FeederState::*;
+
+        let table = [
+            (Idle, Input::CatArrived, Some(CatPresent)),
+            (Idle, Input::CatLeft, None),
+            (Idle, Input::CooldownOver, None),
+            (CatPresent, Input::CatLeft, Some(Cooldown)),
+            (CatPresent, Input::CatArrived, None),
+            (Dispensing, Input::CatLeft, Some(Cooldown)),
+            (Cooldown, Input::CatArrived, Some(CatPresent)),
+            (Cooldown, Input::CooldownOver, Some(Idle)),
+            (Idle, Input::Fault, Some(Fault)),
+            (CatPresent, Input::Fault, Some(Fault)),
+            (Cooldown, Input::Fault, Some(Fault)),
+            (Fault, Input::CatArrived, None),
+            (Fault, Input::CooldownOver, None),
+            (Fault, Input::Fault, None),
+            (Fault, Input::ClearFault, Some(Idle)),
+            (Idle, Input::ClearFault, None),
+            (Idle, Input::StartMaintenance, Some(Maintenance)),
+            (CatPresent, Input::StartMaintenance, Some(Maintenance)),
+            (Fault, Input::StartMaintenance, Some(Maintenance)),
+            (Maintenance, Input::CatArrived, None),
+            (Maintenance, Input::Fault, None),
+            (Maintenance, Input::ClearFault, None),
+            (Maintenance, Input::StartMaintenance, None),
+            (Maintenance, Input::EndMaintenance, Some(Idle)),
+            (Idle, Input::EndMaintenance, None),
+        ];
+        for (state, input, expected) in table.iter() {
+            assert_eq!(
+                transition(*state, *input),
+                *expected,
+                "{:?} on {:?}",
+                state,
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_fault_latches() {
+        let mut feeder = feeder();
+        feeder.fault(Device::LoadCell, "Load cell isn't responding".to_string());
+        assert_eq!(feeder.state(), FeederState::Fault);
+        assert!(feeder.has_fault(Device::LoadCell));
+        assert!(!feeder.has_fault(Device::RfidReader));
+
+        // Nothing moves it on, and the first reason is kept
+        assert!(!feeder.handle(Input::CatArrived));
+        feeder.fault(Device::LoadCell, "Something else".to_string());
+        assert_eq!(feeder.state(), FeederState::Fault);
+        assert_eq!(
+            feeder.faults.get(&Device::LoadCell).map(String::as_str),
+            Some("Load cell isn't responding")
+        );
+
+        feeder.clear_fault();
+        assert_eq!(feeder.state(), FeederState::Idle);
+        assert!(feeder.faults.is_empty());
+    }
+
+    #[test]
+    fn test_fault_clears_on_recovery() {
+        let mut feeder = feeder();
+        feeder.fault(Device::RfidReader, "RFID reader won't open".to_string());
+        feeder.fault(Device::LoadCell, "Load cell isn't responding".to_string());
+
+        // Still faulted while the other device is down
+        feeder.recovered(Device::RfidReader);
+        assert_eq!(feeder.state(), FeederState::Fault);
+        assert!(!feeder.has_fault(Device::RfidReader));
+
+        feeder.recovered(Device::LoadCell);
+        assert_eq!(feeder.state(), FeederState::Idle);
+
+        // Recovering without having failed changes nothing
+        feeder.recovered(Device::LoadCell);
+        assert_eq!(feeder.transitions.len(), 2);
+    }
+
+    #[test]
+    fn test_cooldown_expires() {
+        let mut feeder = feeder();
+        assert!(feeder.handle(Input::CatArrived));
+        assert!(feeder.handle(Input::CatLeft));
+        assert_eq!(feeder.state(), FeederState::Cooldown);
+
+        let now = Instant::now();
+        assert_eq!(feeder.tick(now), None);
+        assert_eq!(feeder.state(), FeederState::Cooldown);
+
+        let later = now + Duration::from_secs(config::Feeder::default().cooldown);
+        assert_eq!(feeder.tick(later), Some(Input::CooldownOver));
+        assert_eq!(feeder.state(), FeederState::Idle);
+    }
+
+    #[test]
+    fn test_transitions_are_recorded() {
+        let mut feeder = feeder();
+        assert!(feeder.handle(Input::CatArrived));
+        assert!(!feeder.handle(Input::CatArrived));
+        assert_eq!(feeder.transitions.len(), 1);
+        let transition = &feeder.transitions[0];
+        assert_eq!(transition.from, FeederState::Idle);
+        assert_eq!(transition.to, FeederState::CatPresent);
+        assert_eq!(transition.input, Input::CatArrived);
+
+        for _ in 0..MAX_TRANSITIONS {
+            assert!(feeder.handle(Input::CatLeft));
+            assert!(feeder.handle(Input::CatArrived));
+        }
+        assert_eq!(feeder.transitions.len(), MAX_TRANSITIONS);
+    }
+
+    #[test]
+    fn test_maintenance_expires() {
+        let mut feeder = feeder();
+        feeder.start_maintenance();
+        assert!(feeder.in_maintenance());
+
+        let now = Instant::now();
+        assert!(feeder.maintenance_remaining(now).is_some());
+        assert_eq!(feeder.tick(now), None);
+
+        let timeout = Duration::from_secs(config::Feeder::default().maintenance_timeout);
+        assert_eq!(
+            feeder.maintenance_remaining(now + timeout),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(feeder.tick(now + timeout), Some(Input::EndMaintenance));
+        assert_eq!(feeder.state(), FeederState::Idle);
+        assert_eq!(feeder.maintenance_remaining(now + timeout), None);
+    }
+
+    #[test]
+    fn test_end_maintenance() {
+        let mut feeder = feeder();
+        assert!(!feeder.end_maintenance());
+        feeder.start_maintenance();
+        assert!(feeder.end_maintenance());
+        assert_eq!(feeder.state(), FeederState::Idle);
+    }
+
+    #[test]
+    fn test_fault_during_maintenance() {
+        let mut feeder = feeder();
+        feeder.start_maintenance();
+        feeder.fault(Device::LoadCell, "Load cell isn't responding".to_string());
+        assert!(feeder.in_maintenance());
+
+        assert!(feeder.end_maintenance());
+        assert_eq!(feeder.state(), FeederState::Fault);
+        assert!(feeder.has_fault(Device::LoadCell));
+    }
+
+    #[test]
+    fn test_maintenance_keeps_fault() {
+        let mut feeder = feeder();
+        feeder.fault(Device::LoadCell, "Load cell isn't responding".to_string());
+        feeder.start_maintenance();
+        assert!(feeder.in_maintenance());
+
+        let timeout = Duration::from_secs(config::Feeder::default().maintenance_timeout);
+        assert_eq!(
+            feeder.tick(Instant::now() + timeout),
+            Some(Input::EndMaintenance)
+        );
+        assert_eq!(feeder.state(), FeederState::Fault);
+    }
+
+    #[test]
+    fn test_clear_fault_during_maintenance() {
+        let mut feeder = feeder();
+        feeder.fault(Device::LoadCell, "Load cell isn't responding".to_string());
+        feeder.start_maintenance();
+        feeder.clear_fault();
+        assert!(feeder.faults.is_empty());
+
+        assert!(feeder.end_maintenance());
+        assert_eq!(feeder.state(), FeederState::Idle);
+    }
+
+    #[test]
+    fn test_clear_fault_with_cat_present() {
+        let mut feeder = feeder();
+        feeder.cat_arrived();
+        feeder.fault(Device::LoadCell, "Load cell isn't responding".to_string());
+        feeder.clear_fault();
+        assert_eq!(feeder.state(), FeederState::CatPresent);
+
+        feeder.cat_left();
+        assert_eq!(feeder.state(), FeederState::Cooldown);
+    }
+
+    #[test]
+    fn test_recovered_with_cat_present() {
+        let mut feeder = feeder();
+        feeder.fault(Device::RfidReader, "RFID reader won't open".to_string());
+        feeder.cat_arrived();
+        assert_eq!(feeder.state(), FeederState::Fault);
+
+        feeder.recovered(Device::RfidReader);
+        assert_eq!(feeder.state(), FeederState::CatPresent);
+    }
+
+    #[test]
+    fn test_end_maintenance_with_cat_present() {
+        let mut feeder = feeder();
+        feeder.start_maintenance();
+        feeder.cat_arrived();
+        assert!(feeder.end_maintenance());
+        assert_eq!(feeder.state(), FeederState::CatPresent);
+
+        feeder.cat_left();
+        assert_eq!(feeder.state(), FeederState::Cooldown);
+    }
+
+    #[test]
+    fn test_end_maintenance_after_cat_left() {
+        let mut feeder = feeder();
+        feeder.cat_arrived();
+        feeder.start_maintenance();
+        feeder.cat_left();
+        assert!(feeder.end_maintenance());
+        assert_eq!(feeder.state(), FeederState::Idle);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Local};
+use serde_derive::Serialize;
+
+/// A distance reading, or the readings over a period averaged together
+#[derive(Clone, Copy, Serialize)]
+pub struct Sample {
+    /// Milli seconds since the unix epoch
+    pub time: i64,
+    pub value: u16,
+    /// Largest reading in the period
+    pub max: u16,
+}
+
+/// How far back to look at the readings
+#[derive(Clone, Copy, PartialEq)]
+pub enum Window {
+    /// Every reading from the last few minutes
+    Recent,
+    /// Ten second averages over the last hour
+    Hour,
+    /// Five minute averages over the last day
+    Day,
+}
+
+impl Window {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "recent" => Some(Window::Recent),
+            "hour" => Some(Window::Hour),
+            "day" => Some(Window::Day),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Window::Recent => "recent",
+            Window::Hour => "hour",
+            Window::Day => "day",
+        }
+    }
+}
+
+// Readings collected for the sample being built
+struct Pending {
+    start: DateTime<Local>,
+    sum: u32,
+    count: u32,
+    max: u16,
+}
+
+/// A ring buffer of samples each covering a bucket of time
+struct Tier {
+    bucket: Duration,
+    capacity: usize,
+    samples: VecDeque<Sample>,
+    pending: Option<Pending>,
+}
+
+impl Tier {
+    fn new(bucket: Duration, capacity: usize) -> Self {
+        Tier {
+            bucket,
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+            pending: None,
+        }
+    }
+
+    fn record(&mut self, time: DateTime<Local>, value: u16) {
+        let pending = self.pending.get_or_insert(Pending {
+            start: time,
+            sum: 0,
+            count: 0,
+            max: 0,
+        });
+        pending.sum += value as u32;
+        pending.count += 1;
+        pending.max = pending.max.max(value);
+
+        if time.signed_duration_since(pending.start) < self.bucket {
+            return;
+        }
+        let sample = Sample {
+            time: pending.start.timestamp_millis(),
+            value: (pending.sum / pending.count) as u16,
+            max: pending.max,
+        };
+        self.pending = None;
+        if self.samples.len() >= self.capacity {
+            let _ = self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+}
+
+/// Time stamped distance readings, downsampled for the longer windows
+pub struct DistanceHistory {
+    /// Enter threshold in counts, for drawing over the readings
+    pub enter_threshold: u16,
+    /// Exit threshold in counts, for drawing over the readings
+    pub exit_threshold: u16,
+    recent: Tier,
+    hour: Tier,
+    day: Tier,
+}
+
+impl DistanceHistory {
+    pub fn new(enter_threshold: u16, exit_threshold: u16) -> Self {
+        DistanceHistory {
+            enter_threshold,
+            exit_threshold,
+            recent: Tier::new(Duration::zero(), 1200),
+            hour: Tier::new(Duration::seconds(10), 360),
+            day: Tier::new(Duration::minutes(5), 288),
+        }
+    }
+
+    pub fn record(&mut self, value: u16) {
+        let now = Local::now();
+        self.recent.record(now, value);
+        self.hour.record(now, value);
+        self.day.record(now, value);
+    }
+
+    /// The samples in the window, oldest first
+    pub fn samples(&self, window: Window) -> Vec<Sample> {
+        let tier = match window {
+            Window::Recent => &self.recent,
+            Window::Hour => &self.hour,
+            Window::Day => &self.day,
+        };
+        tier.samples.iter().copied().collect()
+    }
+}
@@ -0,0 +1,63 @@
+use log::{info, warn};
+
+use crate::config;
+
+/// Percent the level has to rise above the low threshold before the hopper
+/// is no longer low, so a noisy sensor doesn't flip back and forth.
+const HYSTERESIS: f32 = 5.0;
+
+/// Tracks how much food is left in the hopper from the readings of a sensor.
+pub struct Hopper {
+    config: config::Hopper,
+    /// Food left in percent
+    level: Option<f32>,
+    low: bool,
+}
+
+impl Hopper {
+    pub fn new(config: config::Hopper) -> Self {
+        Hopper {
+            config,
+            level: None,
+            low: false,
+        }
+    }
+
+    /// Name of the analog channel measuring the hopper
+    pub fn channel(&self) -> &str {
+        &self.config.channel
+    }
+
+    pub fn level(&self) -> Option<f32> {
+        self.level
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.low
+    }
+
+    /// Update the level from a sensor reading. Returns the new low state when
+    /// it changes.
+    pub fn reading(&mut self, value: f32) -> Option<bool> {
+        let range = self.config.full - self.config.empty;
+        if range == 0.0 {
+            return None;
+        }
+        let level = ((value - self.config.empty) / range * 100.0)
+            .max(0.0)
+            .min(100.0);
+        self.level = Some(level);
+
+        if !self.low && level < self.config.low_threshold {
+            warn!("Hopper is low on food: {:.0}%", level);
+            self.low = true;
+            Some(true)
+        } else if self.low && level >= self.config.low_threshold + HYSTERESIS {
+            info!("Hopper has been refilled: {:.0}%", level);
+            self.low = false;
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
@@ -1,2 +1,3 @@
+mod chart;
 mod helpers;
 pub mod service;
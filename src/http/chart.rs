@@ -0,0 +1,77 @@
+use std::fmt::Write;
+
+use crate::history::Sample;
+
+const WIDTH: f32 = 600.0;
+const HEIGHT: f32 = 200.0;
+
+/// Draw the samples as an SVG line chart with the thresholds across it.
+pub fn distance_chart(samples: &[Sample], enter_threshold: u16, exit_threshold: u16) -> String {
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = WIDTH,
+        h = HEIGHT
+    );
+    svg.push_str(r#"<rect width="100%" height="100%" fill="white" stroke="black"/>"#);
+
+    let top = samples
+        .iter()
+        .map(|sample| sample.max)
+        .chain(vec![enter_threshold, exit_threshold])
+        .max()
+        .unwrap_or(0)
+        .max(1) as f32
+        * 1.1;
+    let y = |value: u16| HEIGHT - value as f32 / top * HEIGHT;
+
+    match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) if samples.len() > 1 => {
+            let span = (last.time - first.time).max(1) as f32;
+            let x = |time: i64| (time - first.time) as f32 / span * WIDTH;
+            let line = |value: fn(&Sample) -> u16| {
+                samples
+                    .iter()
+                    .map(|sample| format!("{:.1},{:.1}", x(sample.time), y(value(sample))))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            };
+            let _ = write!(
+                svg,
+                r#"<polyline points="{}" fill="none" stroke="lightgray"/>"#,
+                line(|sample| sample.max)
+            );
+            let _ = write!(
+                svg,
+                r#"<polyline points="{}" fill="none" stroke="black"/>"#,
+                line(|sample| sample.value)
+            );
+        }
+        _ => {
+            let _ = write!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="middle">No readings yet</text>"#,
+                WIDTH / 2.0,
+                HEIGHT / 2.0
+            );
+        }
+    }
+
+    for (label, value, color) in &[
+        ("enter", enter_threshold, "red"),
+        ("exit", exit_threshold, "blue"),
+    ] {
+        let _ = write!(
+            svg,
+            r#"<line x1="0" y1="{y:.1}" x2="{w}" y2="{y:.1}" stroke="{c}" stroke-dasharray="4"/><text x="4" y="{ty:.1}" fill="{c}" font-size="12">{l} {v}</text>"#,
+            y = y(*value),
+            w = WIDTH,
+            c = color,
+            ty = y(*value) - 2.0,
+            l = label,
+            v = value
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
@@ -15,6 +15,24 @@ pub fn render_template(template: String) -> Result<Response<Body>> {
         .body(body)?)
 }
 
+/// Return the string as a JSON document
+pub fn render_json(json: String) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .header("content-type", "application/json")
+        .header("Cache-Control", "no-cache")
+        .status(StatusCode::OK)
+        .body(Body::from(json))?)
+}
+
+/// Return the string as an SVG image
+pub fn render_svg(svg: String) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .header("content-type", "image/svg+xml")
+        .header("Cache-Control", "no-cache")
+        .status(StatusCode::OK)
+        .body(Body::from(svg))?)
+}
+
 /// Redirect to the given location. Location is lame and just a string right now
 pub fn redirect_to(location: String) -> Result<Response<Body>> {
     let body = Body::from(format!("Navigate to: {}", location));
@@ -1,14 +1,20 @@
 /// my local http service
-use crate::http::helpers;
+use crate::analog::Reading;
+use crate::calibration::CalibrationPoint;
+use crate::history::{Sample, Window};
+use crate::http::{chart, helpers};
 use crate::result::Result;
-use crate::state::{Event, EventTx, State};
+use crate::state::{Event, EventTx, Picture, State};
+use crate::tag::TagId;
 
 use std::collections::HashMap;
 
 use std::sync::Arc;
+use std::time::Instant;
 
 // https://github.com/djc/askama
 use askama::Template;
+use serde_derive::Serialize;
 use log::{debug, error};
 
 //use hyper::service::{make_service_fn, service_fn};
@@ -24,8 +30,68 @@ struct HelloTemplate<'a> {
     click_count: &'a u32,
     loop_count: &'a u32,
     picture_count: usize,
-    last_tag: u32,
-    last_distance: u16,
+    pictures: &'a [Picture],
+    cameras: &'a [u8],
+    last_tag: String,
+    present_tag: String,
+    tag_decode_errors: u32,
+    enrolling: bool,
+    last_distance: String,
+    analog_readings: Vec<&'a Reading>,
+    history_window: &'a str,
+    has_hopper: bool,
+    hopper_level: String,
+    has_bowl_weight: bool,
+    bowl_weight: String,
+    calibrating: bool,
+    calibration_readings: &'a [CalibrationPoint],
+    feeder_state: String,
+    /// Minutes until maintenance mode ends, when it's on
+    maintenance_minutes: Option<u64>,
+    fault: String,
+    /// Recent state changes, newest first
+    transitions: Vec<String>,
+}
+
+/// Maintenance mode served as JSON
+#[derive(Serialize)]
+struct MaintenanceJson {
+    active: bool,
+    /// Seconds until maintenance mode ends on its own
+    remaining_seconds: Option<u64>,
+}
+
+/// Live bowl weight served as JSON
+#[derive(Serialize)]
+struct BowlWeightJson {
+    grams: Option<f32>,
+}
+
+/// Distance readings served as JSON
+#[derive(Serialize)]
+struct DistanceHistoryJson {
+    window: &'static str,
+    enter_threshold: u16,
+    exit_threshold: u16,
+    samples: Vec<Sample>,
+}
+
+#[derive(Template)]
+#[template(path = "visits.html")]
+struct VisitsTemplate {
+    visits: Vec<VisitView>,
+}
+
+/// A visit prepared for display
+struct VisitView {
+    started: String,
+    duration: String,
+    tag: String,
+    /// Current indexes of the pictures taken on entering
+    before: Vec<usize>,
+    /// Current indexes of the pictures taken on leaving
+    after: Vec<usize>,
+    eaten: String,
 }
 
 #[derive(Template)]
@@ -33,6 +99,7 @@ struct HelloTemplate<'a> {
 struct PictureTemplate {
     image_id: usize,
     total_images: usize,
+    camera: u8,
 }
 
 // The webserver task
@@ -64,8 +131,64 @@ pub async fn http_response(
                 click_count: &state.click_count,
                 loop_count: &state.loop_count,
                 picture_count: state.pictures.len(),
-                last_tag: state.last_tag_read().unwrap_or(0),
-                last_distance: state.distance,
+                pictures: &state.pictures,
+                cameras: &state.cameras,
+                last_tag: state
+                    .last_tag_read()
+                    .map_or_else(|| "None".to_string(), |tag| tag_formats(&tag)),
+                present_tag: state
+                    .present_tag
+                    .map_or_else(|| "None".to_string(), |tag| tag.to_string()),
+                tag_decode_errors: state.tag_decode_errors,
+                enrolling: state.is_enrolling(),
+                last_distance: match state.distance_cm() {
+                    Some(cm) => format!("{} ({:.1} cm)", state.distance, cm),
+                    None => state.distance.to_string(),
+                },
+                analog_readings: state.analog_readings.values().collect(),
+                history_window: history_window(&req).name(),
+                has_hopper: state.hopper.is_some(),
+                hopper_level: match &state.hopper {
+                    Some(hopper) => match hopper.level() {
+                        Some(level) if hopper.is_low() => format!("{:.0}% (low)", level),
+                        Some(level) => format!("{:.0}%", level),
+                        None => "No reading yet".to_string(),
+                    },
+                    None => String::new(),
+                },
+                has_bowl_weight: state.bowl_weight.is_some(),
+                bowl_weight: state
+                    .bowl_weight
+                    .map_or_else(String::new, |grams| format!("{:.1}", grams)),
+                calibrating: state.calibration_readings.is_some(),
+                calibration_readings: state.calibration_readings.as_deref().unwrap_or(&[]),
+                feeder_state: state.feeder.state().to_string(),
+                maintenance_minutes: state
+                    .feeder
+                    .maintenance_remaining(Instant::now())
+                    .map(|remaining| (remaining.as_secs() + 59) / 60),
+                fault: state
+                    .feeder
+                    .faults
+                    .values()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(". "),
+                transitions: state
+                    .feeder
+                    .transitions
+                    .iter()
+                    .rev()
+                    .map(|transition| {
+                        format!(
+                            "{} {} -> {} ({:?})",
+                            transition.at.format("%H:%M:%S"),
+                            transition.from,
+                            transition.to,
+                            transition.input
+                        )
+                    })
+                    .collect(),
             };
             let template = hello.render()?;
             helpers::render_template(template)
@@ -97,21 +220,194 @@ pub async fn http_response(
             }
             helpers::redirect_to("/".to_string())
         }
+        (&Method::POST, &["clear_fault"]) => {
+            if let Err(err) = tx.send(Event::ClearFault) {
+                error!("Error clearing fault: {}", err);
+            }
+            helpers::redirect_to("/".to_string())
+        }
+        (&Method::GET, &["maintenance"]) => {
+            let remaining = state
+                .lock()
+                .await
+                .feeder
+                .maintenance_remaining(Instant::now());
+            let json = serde_json::to_string(&MaintenanceJson {
+                active: remaining.is_some(),
+                remaining_seconds: remaining.map(|remaining| remaining.as_secs()),
+            })?;
+            helpers::render_json(json)
+        }
+        (&Method::POST, &["maintenance", "start"]) => {
+            if let Err(err) = tx.send(Event::StartMaintenance) {
+                error!("Error starting maintenance: {}", err);
+            }
+            helpers::redirect_to("/".to_string())
+        }
+        (&Method::POST, &["maintenance", "end"]) => {
+            if let Err(err) = tx.send(Event::EndMaintenance) {
+                error!("Error ending maintenance: {}", err);
+            }
+            helpers::redirect_to("/".to_string())
+        }
+        (&Method::POST, &["enroll"]) => {
+            if let Err(err) = tx.send(Event::StartEnrollment) {
+                error!("Error starting enrollment: {}", err);
+            }
+            helpers::redirect_to("/".to_string())
+        }
+        (&Method::POST, &["cancel_enrollment"]) => {
+            if let Err(err) = tx.send(Event::CancelEnrollment) {
+                error!("Error cancelling enrollment: {}", err);
+            }
+            helpers::redirect_to("/".to_string())
+        }
+        (&Method::POST, &["calibration", "start"]) => {
+            if let Err(err) = tx.send(Event::StartCalibration) {
+                error!("Error starting calibration: {}", err);
+            }
+            helpers::redirect_to("/".to_string())
+        }
+        (&Method::POST, &["calibration", "record"]) => {
+            let b = hyper::body::to_bytes(req).await?;
+            let params = form_urlencoded::parse(b.as_ref())
+                .into_owned()
+                .collect::<HashMap<String, String>>();
+            match params.get("cm").and_then(|cm| cm.parse::<f32>().ok()) {
+                Some(cm) => {
+                    if let Err(err) = tx.send(Event::RecordCalibration(cm)) {
+                        error!("Error recording calibration: {}", err);
+                    }
+                    helpers::redirect_to("/".to_string())
+                }
+                None => helpers::unprocessable_entry(),
+            }
+        }
+        (&Method::POST, &["calibration", "save"]) => {
+            if let Err(err) = tx.send(Event::SaveCalibration) {
+                error!("Error saving calibration: {}", err);
+            }
+            helpers::redirect_to("/".to_string())
+        }
+        (&Method::POST, &["calibration", "cancel"]) => {
+            if let Err(err) = tx.send(Event::CancelCalibration) {
+                error!("Error cancelling calibration: {}", err);
+            }
+            helpers::redirect_to("/".to_string())
+        }
+        (&Method::GET, &["distance_history"]) => {
+            let window = history_window(&req);
+            let state = state.lock().await;
+            let history = &state.distance_history;
+            let json = serde_json::to_string(&DistanceHistoryJson {
+                window: window.name(),
+                enter_threshold: history.enter_threshold,
+                exit_threshold: history.exit_threshold,
+                samples: history.samples(window),
+            })?;
+            helpers::render_json(json)
+        }
+        (&Method::GET, &["distance_chart.svg"]) => {
+            let window = history_window(&req);
+            let state = state.lock().await;
+            let history = &state.distance_history;
+            let svg = chart::distance_chart(
+                &history.samples(window),
+                history.enter_threshold,
+                history.exit_threshold,
+            );
+            helpers::render_svg(svg)
+        }
+        (&Method::GET, &["bowl_weight"]) => {
+            let grams = state.lock().await.bowl_weight;
+            let json = serde_json::to_string(&BowlWeightJson { grams })?;
+            helpers::render_json(json)
+        }
+        (&Method::POST, &["scale", "tare"]) => {
+            if let Err(err) = tx.send(Event::TareScale) {
+                error!("Error taring scale: {}", err);
+            }
+            helpers::redirect_to("/".to_string())
+        }
+        (&Method::POST, &["scale", "calibrate"]) => {
+            let b = hyper::body::to_bytes(req).await?;
+            let params = form_urlencoded::parse(b.as_ref())
+                .into_owned()
+                .collect::<HashMap<String, String>>();
+            match params.get("grams").and_then(|grams| grams.parse::<f32>().ok()) {
+                Some(grams) => {
+                    if let Err(err) = tx.send(Event::CalibrateScale(grams)) {
+                        error!("Error calibrating scale: {}", err);
+                    }
+                    helpers::redirect_to("/".to_string())
+                }
+                None => helpers::unprocessable_entry(),
+            }
+        }
         (&Method::GET, &["favicon.ico"]) => helpers::get_png("cat-icon_64.png"),
         (&Method::POST, &["take_picture"]) => {
-            let has_camera = { state.lock().await.has_camera };
+            let has_camera = { state.lock().await.has_camera() };
             match has_camera {
                 false => helpers::not_found(), // Should have a nice message about taking a picture or not
                 true => {
+                    let b = hyper::body::to_bytes(req).await?;
+                    let params = form_urlencoded::parse(b.as_ref())
+                        .into_owned()
+                        .collect::<HashMap<String, String>>();
+                    // A missing or unparsable camera means take a picture with all of them
+                    let camera = params
+                        .get("camera")
+                        .and_then(|camera| camera.parse::<u8>().ok());
+
                     // Send message to take picture
-                    debug!("Requesting image be taken");
-                    if let Err(err) = tx.send(Event::TakeImageRequest) {
+                    debug!("Requesting image be taken with camera {:?}", camera);
+                    if let Err(err) = tx.send(Event::TakeImageRequest(camera)) {
                         error!("Error taking picture: {}", err);
                     };
                     helpers::redirect_to("/".to_string())
                 }
             }
         }
+        (&Method::GET, &["visits"]) => {
+            let state = state.lock().await;
+            let visits = state
+                .visits
+                .iter()
+                .rev()
+                .map(|visit| VisitView {
+                    started: visit.started.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    duration: match visit.ended {
+                        Some(ended) => {
+                            format!("{} seconds", (ended - visit.started).num_seconds())
+                        }
+                        None => "In progress".to_string(),
+                    },
+                    tag: match visit.tag {
+                        Some(tag) => match state.cat_name(&tag) {
+                            Some(name) => format!("{} ({})", name, tag),
+                            None => tag.to_string(),
+                        },
+                        None => "No tag read".to_string(),
+                    },
+                    before: visit
+                        .before
+                        .iter()
+                        .filter_map(|id| state.picture_index(*id))
+                        .collect(),
+                    after: visit
+                        .after
+                        .iter()
+                        .filter_map(|id| state.picture_index(*id))
+                        .collect(),
+                    eaten: visit.eaten().map_or_else(
+                        || "Not weighed".to_string(),
+                        |grams| format!("{:.1} g", grams),
+                    ),
+                })
+                .collect();
+            let template = VisitsTemplate { visits }.render()?;
+            helpers::render_template(template)
+        }
         (&Method::GET, &["picture", numb]) => match numb.parse::<usize>() {
             Ok(image_id) => {
                 let picts = &state.lock().await.pictures;
@@ -119,6 +415,7 @@ pub async fn http_response(
                     let picture_template = PictureTemplate {
                         image_id,
                         total_images: picts.len(),
+                        camera: picts[image_id].camera,
                     };
                     let template = picture_template.render()?;
                     helpers::render_template(template)
@@ -135,7 +432,7 @@ pub async fn http_response(
                     let picts = &state.lock().await.pictures;
                     if picts.len() > 0 && image_id < picts.len() {
                         let some_pict = &picts[image_id];
-                        helpers::get_camera_image(some_pict.to_vec())
+                        helpers::get_camera_image(some_pict.image.to_vec())
                     } else {
                         helpers::not_found()
                     }
@@ -164,3 +461,23 @@ pub async fn http_response(
         }
     }
 }
+
+/// The tag in each of the formats it might be printed as
+fn tag_formats(tag: &TagId) -> String {
+    match tag.facility_card() {
+        Some(facility_card) => format!("{} ({}, {})", tag.decimal(), facility_card, tag.hex()),
+        None => format!("{} ({})", tag.decimal(), tag.hex()),
+    }
+}
+
+/// The history window asked for in the query string. Defaults to the recent readings.
+fn history_window(req: &Request<Body>) -> Window {
+    req.uri()
+        .query()
+        .and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "window")
+                .and_then(|(_, value)| Window::from_name(&value))
+        })
+        .unwrap_or(Window::Recent)
+}
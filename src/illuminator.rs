@@ -0,0 +1,81 @@
+use crate::analog::Reading;
+use crate::config;
+
+use std::collections::BTreeMap;
+
+use chrono::Timelike;
+use log::{debug, error};
+use rppal::gpio::{Gpio, OutputPin};
+use tokio::time::{delay_for, Duration};
+
+/// An IR LED or light switched on by a GPIO pin while pictures are taken.
+pub struct Illuminator {
+    pin: OutputPin,
+    config: config::Illuminator,
+}
+
+impl Illuminator {
+    /// Claim the configured pin. Returns None if the pin can't be opened.
+    pub fn new(config: config::Illuminator) -> Option<Self> {
+        let pin = Gpio::new().and_then(|gpios| gpios.get(config.pin));
+        match pin {
+            Ok(pin) => Some(Illuminator {
+                pin: pin.into_output(),
+                config,
+            }),
+            Err(err) => {
+                error!("Unable to open illuminator pin {}: {}", config.pin, err);
+                None
+            }
+        }
+    }
+
+    /// Last reading of the configured light sensor
+    pub fn light_level(&self, readings: &BTreeMap<String, Reading>) -> Option<f32> {
+        self.config
+            .light_channel
+            .as_ref()
+            .and_then(|name| readings.get(name))
+            .map(|reading| reading.value)
+    }
+
+    /// Switch the light on if it's dark, and wait for the lead time so the
+    /// camera can adjust.
+    pub async fn light_up(&mut self, light_level: Option<f32>) {
+        if self.is_dark(light_level) {
+            debug!("Switching on illuminator");
+            self.pin.set_high();
+            delay_for(Duration::from_millis(self.config.lead_time)).await;
+        }
+    }
+
+    /// Switch the light off
+    pub fn switch_off(&mut self) {
+        debug!("Switching off illuminator");
+        self.pin.set_low();
+    }
+
+    // A light sensor reading takes precedence over the night hours.
+    // Without either the light is always used.
+    fn is_dark(&self, light_level: Option<f32>) -> bool {
+        if let (Some(_), Some(threshold)) =
+            (&self.config.light_channel, self.config.light_threshold)
+        {
+            // No reading yet, assume the worst
+            return light_level.map_or(true, |level| level < threshold);
+        }
+
+        match (self.config.night_start, self.config.night_end) {
+            (Some(start), Some(end)) => {
+                let hour = chrono::Local::now().hour() as u8;
+                if start <= end {
+                    hour >= start && hour < end
+                } else {
+                    // Night wraps past midnight
+                    hour >= start || hour < end
+                }
+            }
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use crate::state::EnrollmentRx;
+
+use log::{debug, error};
+use rppal::gpio::Gpio;
+use tokio::task;
+
+/// Light an LED on the given pin while enrollment mode is waiting for a tag.
+pub fn create_enrollment_led_task(mut rx: EnrollmentRx, pin: u8) -> task::JoinHandle<()> {
+    task::spawn(async move {
+        let mut led = match Gpio::new().and_then(|gpios| gpios.get(pin)) {
+            Ok(pin) => pin.into_output(),
+            Err(err) => {
+                error!("Unable to open enrollment LED pin {}: {}", pin, err);
+                return;
+            }
+        };
+
+        // Ends when the reducer shuts down and drops the sender
+        while let Some(enrolling) = rx.recv().await {
+            if enrolling {
+                led.set_high();
+            } else {
+                led.set_low();
+            }
+        }
+        led.set_low();
+        debug!("Ending enrollment LED task");
+    })
+}
@@ -0,0 +1,155 @@
+use crate::config::{self, PresenceSensor};
+use crate::distance;
+use crate::state::{Action, ActionRx, State};
+use crate::utils::wait_until;
+
+use std::sync::Arc;
+
+use futures::{
+    future::FutureExt, // for `.fuse()`
+    select,
+};
+use log::{debug, error, info, warn};
+use rppal::pwm::{Channel, Polarity, Pwm};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task;
+use tokio::time::{delay_for, Duration, Instant};
+
+/// Servos expect a pulse every 20ms
+const SERVO_PERIOD: Duration = Duration::from_millis(20);
+
+/// Steps the lid takes to close, checking for a pinch between each
+const CLOSE_STEPS: u64 = 10;
+
+/// Time each closing step takes
+const STEP_TIME: Duration = Duration::from_millis(50);
+
+/// Time to wait before closing again after something was in the way
+const PINCH_RETRY: Duration = Duration::from_secs(2);
+
+/// Shorthand for the send half of the lid command channel.
+pub type LidTx = mpsc::UnboundedSender<LidCommand>;
+
+/// Shorthand for the recieve half of the lid command channel.
+pub type LidRx = mpsc::UnboundedReceiver<LidCommand>;
+
+/// What the lid should do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LidCommand {
+    Open,
+    /// Close once the grace period is over
+    CloseAfterGrace,
+    /// Close without waiting for the grace period
+    CloseNow,
+}
+
+/// Drive the lid servo. Closing stops and backs off a step if the distance
+/// sensor sees something under the lid, then tries again. After
+/// `pinch_retries` tries it waits where it is until clear, so a cat it didn't
+/// open for is never let back in by staying at the bowl.
+pub fn create_lid_task(
+    mut rx: ActionRx,
+    mut lid_rx: LidRx,
+    lid_config: config::Lid,
+    sensor: PresenceSensor,
+    state: Arc<Mutex<State>>,
+) -> task::JoinHandle<()> {
+    task::spawn_local(async move {
+        let channel = match lid_config.pwm_channel {
+            0 => Channel::Pwm0,
+            1 => Channel::Pwm1,
+            channel => {
+                error!("Invalid PWM channel {}", channel);
+                return;
+            }
+        };
+        let open = Duration::from_micros(lid_config.open_pulse);
+        let closed = Duration::from_micros(lid_config.closed_pulse);
+        let pwm = match Pwm::with_period(channel, SERVO_PERIOD, closed, Polarity::Normal, true) {
+            Ok(pwm) => pwm,
+            Err(err) => {
+                error!("Unable to open lid servo: {}", err);
+                return;
+            }
+        };
+
+        let grace_period = Duration::from_millis(lid_config.grace_period);
+        // Closing steps taken. 0 is open.
+        let mut position = CLOSE_STEPS;
+        // Times in a row something was under the lid while closing
+        let mut pinches = 0;
+        let mut close_at: Option<Instant> = None;
+        loop {
+            select! {
+                _ = wait_until(close_at).fuse() => {
+                    close_at = None;
+                    if position == CLOSE_STEPS {
+                        continue;
+                    }
+                    info!("Closing lid");
+                    let mut pinched = false;
+                    while position < CLOSE_STEPS {
+                        let distance = state.lock().await.distance;
+                        if distance::is_within(sensor, distance, lid_config.pinch_threshold) {
+                            pinched = true;
+                            break;
+                        }
+                        position += 1;
+                        set_pulse(&pwm, closing_pulse(&lid_config, position));
+                        delay_for(STEP_TIME).await;
+                    }
+                    if !pinched {
+                        pinches = 0;
+                        continue;
+                    }
+                    pinches += 1;
+                    if pinches <= lid_config.pinch_retries && position > 0 {
+                        warn!("Something is under the lid. Backing off");
+                        position -= 1;
+                        set_pulse(&pwm, closing_pulse(&lid_config, position));
+                    } else {
+                        warn!("Something is still under the lid. Waiting for it to clear");
+                    }
+                    close_at = Some(Instant::now() + PINCH_RETRY);
+                }
+                command = lid_rx.recv().fuse() => {
+                    match command {
+                        Some(LidCommand::Open) => {
+                            close_at = None;
+                            pinches = 0;
+                            if position != 0 {
+                                info!("Opening lid");
+                                position = 0;
+                                set_pulse(&pwm, open);
+                            }
+                        }
+                        Some(LidCommand::CloseAfterGrace) => {
+                            close_at = Some(Instant::now() + grace_period);
+                        }
+                        Some(LidCommand::CloseNow) => close_at = Some(Instant::now()),
+                        // The reducer has shut down
+                        None => break,
+                    }
+                }
+                action = rx.recv().fuse() => if let Some(Action::Shutdown) = action {
+                    debug!("Shut down lid task");
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Pulse width of the servo part way through closing
+fn closing_pulse(lid_config: &config::Lid, step: u64) -> Duration {
+    let open = lid_config.open_pulse as i64;
+    let closed = lid_config.closed_pulse as i64;
+    let pulse = open + (closed - open) * step as i64 / CLOSE_STEPS as i64;
+    Duration::from_micros(pulse as u64)
+}
+
+fn set_pulse(pwm: &Pwm, pulse: Duration) {
+    if let Err(err) = pwm.set_pulse_width(pulse) {
+        error!("Error moving lid servo: {}", err);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::config::{self, LoadCell, ScaleCalibration};
+use crate::feeder::Device;
+use crate::state::{Action, ActionRx, Event, EventTx};
+
+mod hx711;
+
+use hx711::Hx711;
+
+use futures::{
+    future::FutureExt, // for `.fuse()`
+    select,
+};
+use log::{debug, error, info, warn};
+use rppal::gpio::Gpio;
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio::time::{delay_for, Duration};
+
+/// Weighings in a row that can fail before the load cell is a fault
+const MAX_FAILED_READS: u32 = 5;
+
+/// Shorthand for the send half of the scale command channel.
+pub type ScaleTx = mpsc::UnboundedSender<ScaleCommand>;
+
+/// Shorthand for the recieve half of the scale command channel.
+pub type ScaleRx = mpsc::UnboundedReceiver<ScaleCommand>;
+
+/// Requests to adjust the scale. They wait in the channel while the bowl is
+/// being weighed.
+#[derive(Clone, Copy, Debug)]
+pub enum ScaleCommand {
+    /// Zero the scale at the current weight
+    Tare,
+    /// Calibrate the scale with the given grams in the bowl
+    Calibrate(f32),
+}
+
+/// Weigh the bowl with an HX711 load cell and handle taring and calibrating it.
+pub fn create_load_cell_task(
+    mut rx: ActionRx,
+    mut scale_rx: ScaleRx,
+    load_cell_config: LoadCell,
+    event_tx: EventTx,
+) -> task::JoinHandle<()> {
+    task::spawn_local(async move {
+        let pins = Gpio::new().and_then(|gpios| {
+            let clock = gpios.get(load_cell_config.clock_pin)?.into_output();
+            let data = gpios.get(load_cell_config.data_pin)?.into_input();
+            Ok((clock, data))
+        });
+        let mut hx711 = match pins {
+            Ok((clock, data)) => Hx711::new(clock, data),
+            Err(err) => {
+                error!("Unable to open load cell pins: {}", err);
+                return;
+            }
+        };
+
+        let mut calibration = config::read_scale().unwrap_or(ScaleCalibration {
+            offset: load_cell_config.offset,
+            scale: load_cell_config.scale,
+        });
+        let interval = Duration::from_millis(load_cell_config.interval);
+        let samples = load_cell_config.samples;
+        let mut last_raw: Option<f32> = None;
+        let mut failed_reads = 0;
+        loop {
+            select! {
+                _ = delay_for(interval).fuse() => {
+                    // Reading the HX711 is timing sensitive, so keep it off the event loop
+                    let weighed = task::spawn_blocking(move || {
+                        let raw = hx711.read_average(samples);
+                        (hx711, raw)
+                    })
+                    .await;
+                    match weighed {
+                        Ok((returned, raw)) => {
+                            hx711 = returned;
+                            match raw {
+                                Some(raw) => {
+                                    if failed_reads >= MAX_FAILED_READS {
+                                        info!("Load cell is responding again");
+                                        let recovered = Event::Recovered(Device::LoadCell);
+                                        if let Err(err) = event_tx.send(recovered) {
+                                            error!("Error sending recovery: {}", err);
+                                        }
+                                    }
+                                    failed_reads = 0;
+                                    last_raw = Some(raw);
+                                    let grams = (raw - calibration.offset) / calibration.scale;
+                                    if let Err(err) = event_tx.send(Event::BowlWeight(grams)) {
+                                        error!("Error sending event: {}", err);
+                                    }
+                                }
+                                None => {
+                                    warn!("Load cell isn't ready");
+                                    failed_reads += 1;
+                                    // Only once for each run of failures
+                                    if failed_reads == MAX_FAILED_READS {
+                                        let reason = "Load cell isn't responding".to_string();
+                                        let fault = Event::Fault(Device::LoadCell, reason);
+                                        if let Err(err) = event_tx.send(fault) {
+                                            error!("Error sending fault: {}", err);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            error!("Error weighing the bowl: {}", err);
+                            break;
+                        }
+                    }
+                }
+                command = scale_rx.recv().fuse() => {
+                    match command {
+                        Some(ScaleCommand::Tare) => match last_raw {
+                            Some(raw) => {
+                                info!("Tared the scale at {}", raw);
+                                calibration.offset = raw;
+                                save_scale(&calibration);
+                            }
+                            None => error!("Can't tare the scale without a reading"),
+                        },
+                        Some(ScaleCommand::Calibrate(grams)) => match last_raw {
+                            Some(raw) if grams > 0.0 && raw != calibration.offset => {
+                                calibration.scale = (raw - calibration.offset) / grams;
+                                info!(
+                                    "Calibrated the scale to {} counts per gram",
+                                    calibration.scale
+                                );
+                                save_scale(&calibration);
+                            }
+                            _ => error!("Can't calibrate the scale with {} grams", grams),
+                        },
+                        // The reducer has shut down
+                        None => break,
+                    }
+                }
+                action = rx.recv().fuse() => if let Some(Action::Shutdown) = action {
+                    debug!("Shut down load cell task");
+                    break;
+                }
+            }
+        }
+    })
+}
+
+fn save_scale(calibration: &ScaleCalibration) {
+    if let Err(err) = config::save_scale(calibration) {
+        error!("Error saving scale calibration: {}", err);
+    }
+}
@@ -0,0 +1,71 @@
+// The HX711 pulls DOUT low when a conversion is ready. Each clock pulse then
+// shifts out a bit, most significant first, and the pulses after the 24th
+// pick the channel and gain of the next conversion.
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{InputPin, OutputPin};
+
+/// Longest a conversion takes at 10 samples a second
+const READY_TIMEOUT: Duration = Duration::from_millis(150);
+
+pub struct Hx711 {
+    clock: OutputPin,
+    data: InputPin,
+}
+
+impl Hx711 {
+    pub fn new(clock: OutputPin, data: InputPin) -> Self {
+        let mut hx711 = Hx711 { clock, data };
+        // Leaving the clock high for over 60µs powers the chip down
+        hx711.clock.set_low();
+        hx711
+    }
+
+    /// Average of a number of raw readings. None if the chip never became ready.
+    pub fn read_average(&mut self, samples: usize) -> Option<f32> {
+        let readings = (0..samples.max(1))
+            .filter_map(|_| self.read())
+            .collect::<Vec<i32>>();
+        if readings.is_empty() {
+            None
+        } else {
+            Some(readings.iter().map(|value| *value as f32).sum::<f32>() / readings.len() as f32)
+        }
+    }
+
+    /// A single raw reading from channel A at a gain of 128
+    fn read(&mut self) -> Option<i32> {
+        let start = Instant::now();
+        while self.data.is_high() {
+            if start.elapsed() > READY_TIMEOUT {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut value: u32 = 0;
+        for _ in 0..24 {
+            self.pulse();
+            value = (value << 1) | self.data.is_high() as u32;
+        }
+        // One more pulse selects channel A at a gain of 128 for the next reading
+        self.pulse();
+
+        // Sign extend the 24 bit two's complement value
+        Some(((value << 8) as i32) >> 8)
+    }
+
+    // The pulse has to stay well under 60µs, too short for thread::sleep
+    fn pulse(&mut self) {
+        self.clock.set_high();
+        spin(Duration::from_micros(1));
+        self.clock.set_low();
+        spin(Duration::from_micros(1));
+    }
+}
+
+fn spin(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {}
+}
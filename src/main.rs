@@ -16,8 +16,6 @@ use futures::{
 
 use log::{debug, error, info};
 
-use rppal::gpio::{Gpio, Level, Trigger};
-
 /* use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use tokio::prelude::*; */
@@ -28,15 +26,41 @@ use tokio::task;
 use tokio::time::delay_for;
 
 // Local Code
+mod analog;
+use analog::create_analog_task;
+
 mod assets;
 
+mod button;
+use button::create_button_task;
+
+mod calibration;
+use calibration::Calibration;
+
 mod config;
 
 mod camera;
 use camera::create_picture_task;
 
+mod capture_policy;
+
 mod distance;
-use distance::create_distance_task;
+
+mod feeder;
+
+mod history;
+
+mod hopper;
+
+mod illuminator;
+
+mod led;
+
+mod lid;
+
+mod load_cell;
+
+mod notifier;
 
 mod result;
 use result::Result;
@@ -44,7 +68,9 @@ use result::Result;
 mod rfid_reader;
 
 mod state;
-use state::{reducer_task, ActionRx, ActionTx, Event, EventRx, EventTx, State};
+use state::{reducer_task, ActionRx, ActionTx, Event, EventRx, EventTx, Senders, State};
+
+mod tag;
 
 mod utils;
 
@@ -54,14 +80,13 @@ use crate::http::service;
 fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     pretty_env_logger::init();
 
-    let config = config::read_config();
+    let mut config = config::read_config();
 
     let addr: SocketAddr = config.listen_port.parse()?;
 
     //let addr: SocketAddr = "0.0.0.0:1337".parse()?;
 
     let mut rt = Runtime::new()?;
-    let gpios = Gpio::new()?;
 
     let local = task::LocalSet::new();
 
@@ -69,34 +94,100 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 
     let (action_tx, mut action_rx): (ActionTx, ActionRx) = watch::channel(state::Action::Startup);
 
-    let state = Arc::new(tokio::sync::Mutex::new(State::new()));
+    let calibration = Calibration::new(
+        config::read_calibration().unwrap_or_else(|| config.distance.calibration.clone()),
+    );
+    let thresholds = distance::thresholds(&config.distance, &calibration)?;
+    distance::check_filter(&config.distance)?;
+    let (thresholds_tx, thresholds_rx) = watch::channel(thresholds);
 
-    let _ = local.block_on(&mut rt, async move {
-        let reducer_task = reducer_task(Arc::clone(&state), rx, action_tx);
+    let state = Arc::new(tokio::sync::Mutex::new(State::new(
+        &config,
+        config::read_registry(),
+        calibration,
+        thresholds,
+    )));
 
-        let picture_task = create_picture_task(action_rx.clone(), tx.clone());
+    let _ = local.block_on(&mut rt, async move {
+        let (enrollment_tx, enrollment_led_task) = match config.rfid.enrollment_led {
+            Some(pin) => {
+                let (enrollment_tx, enrollment_rx) = watch::channel(false);
+                let task = led::create_enrollment_led_task(enrollment_rx, pin);
+                (Some(enrollment_tx), Some(task))
+            }
+            None => (None, None),
+        };
+
+        let (notification_tx, notification_rx) = mpsc::unbounded_channel();
+        let notifier_task = notifier::create_notifier_task(notification_rx, config.notify.take());
+
+        let (scale_tx, load_cell_task) = match config.load_cell.take() {
+            Some(load_cell) => {
+                let (scale_tx, scale_rx) = mpsc::unbounded_channel();
+                let task = load_cell::create_load_cell_task(
+                    action_rx.clone(),
+                    scale_rx,
+                    load_cell,
+                    tx.clone(),
+                );
+                (Some(scale_tx), Some(task))
+            }
+            None => (None, None),
+        };
+
+        let (lid_tx, lid_task) = match config.lid.take() {
+            Some(lid) => {
+                let (lid_tx, lid_rx) = mpsc::unbounded_channel();
+                let task = lid::create_lid_task(
+                    action_rx.clone(),
+                    lid_rx,
+                    lid,
+                    config.distance.sensor,
+                    Arc::clone(&state),
+                );
+                (Some(lid_tx), Some(task))
+            }
+            None => (None, None),
+        };
+
+        let senders = Senders {
+            actions: action_tx,
+            enrollment: enrollment_tx,
+            thresholds: thresholds_tx,
+            notifications: notification_tx,
+            scale: scale_tx,
+            lid: lid_tx,
+        };
+        let reducer_task = reducer_task(Arc::clone(&state), rx, senders);
+
+        let picture_task = create_picture_task(
+            action_rx.clone(),
+            config.images,
+            config.illuminator,
+            Arc::clone(&state),
+            tx.clone(),
+        );
 
         let looping_task = looping_state(tx.clone(), action_rx.clone(), Arc::clone(&state));
 
-        let rfid_reader_task = rfid_reader::rfid_reader(tx.clone(), action_rx.clone());
+        let rfid_reader_task = rfid_reader::rfid_reader(tx.clone(), action_rx.clone(), config.rfid);
+
+        let distance_task = distance::create_distance_task(
+            action_rx.clone(),
+            config.distance.clone(),
+            thresholds_rx.clone(),
+            tx.clone(),
+        );
 
-        let distance_task = create_distance_task(action_rx.clone(), config.distance, tx.clone());
+        let analog_task = create_analog_task(
+            action_rx.clone(),
+            config.distance,
+            thresholds_rx,
+            config.analog,
+            tx.clone(),
+        );
 
-        let button = gpios.get(20).unwrap().into_input_pulldown();
-        let button_tx = tx.clone();
-        let button_listener =
-            utils::watch_pin(button, Trigger::Both, action_rx.clone(), move |i| match i {
-                Level::High => {
-                    info!("Caught a high edge here");
-                    if let Err(err) = button_tx.send(Event::IncClick) {
-                        error!("Error sending click: {}", err)
-                    }
-                }
-                Level::Low => {
-                    info!("Caught a low edge here");
-                    ()
-                }
-            });
+        let button_listener = create_button_task(action_rx.clone(), config.button, tx.clone());
 
         let service_tx = tx.clone();
         let clone_state = Arc::clone(&state);
@@ -142,10 +233,39 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 
         info!("Starting Services");
 
+        let enrollment_led_task = async move {
+            if let Some(task) = enrollment_led_task {
+                let _ = task.await;
+            }
+        };
+
+        let distance_task = async move {
+            if let Some(task) = distance_task {
+                let _ = task.await;
+            }
+        };
+
+        let load_cell_task = async move {
+            if let Some(task) = load_cell_task {
+                let _ = task.await;
+            }
+        };
+
+        let lid_task = async move {
+            if let Some(task) = lid_task {
+                let _ = task.await;
+            }
+        };
+
         let _ret = join!(
+            analog_task,
             button_listener,
             distance_task,
+            enrollment_led_task,
+            lid_task,
+            load_cell_task,
             looping_task,
+            notifier_task,
             picture_task,
             quit_listener,
             reducer_task,
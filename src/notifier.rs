@@ -0,0 +1,55 @@
+use std::fmt;
+
+use log::{debug, error, warn};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::task;
+
+use crate::config::Notify;
+
+/// Shorthand for the send half of the notification channel.
+pub type NotificationTx = mpsc::UnboundedSender<Notification>;
+
+/// Shorthand for the recieve half of the notification channel.
+pub type NotificationRx = mpsc::UnboundedReceiver<Notification>;
+
+/// Something the people looking after the cats should hear about
+#[derive(Clone, Copy, Debug)]
+pub enum Notification {
+    /// The hopper has run low on food, or been refilled
+    LowFood(bool),
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notification::LowFood(true) => write!(f, "The hopper is running low on food"),
+            Notification::LowFood(false) => write!(f, "The hopper has been refilled"),
+        }
+    }
+}
+
+/// Log each notification and pass it on to the configured command. Ends when
+/// the reducer shuts down and drops the sender.
+pub fn create_notifier_task(
+    mut rx: NotificationRx,
+    notify_config: Option<Notify>,
+) -> task::JoinHandle<()> {
+    task::spawn(async move {
+        while let Some(notification) = rx.recv().await {
+            warn!("{}", notification);
+            if let Some(notify_config) = &notify_config {
+                let status = Command::new(&notify_config.command)
+                    .arg(notification.to_string())
+                    .status()
+                    .await;
+                match status {
+                    Ok(status) if status.success() => (),
+                    Ok(status) => error!("Notify command failed with {}", status),
+                    Err(err) => error!("Unable to run notify command: {}", err),
+                }
+            }
+        }
+        debug!("Ending notifier task");
+    })
+}
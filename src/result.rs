@@ -6,6 +6,7 @@ pub mod error {
         HyperError(hyper::error::Error),
         HttpError(http::Error),
         TemplateError(askama::Error),
+        JsonError(serde_json::Error),
     }
 
     impl std::error::Error for Error {}
@@ -34,6 +35,12 @@ pub mod error {
         }
     }
 
+    impl std::convert::From<serde_json::Error> for Error {
+        fn from(err: serde_json::Error) -> Self {
+            Error::JsonError(err)
+        }
+    }
+
     impl std::fmt::Display for Error {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             match self {
@@ -41,6 +48,7 @@ pub mod error {
                 Error::HyperError(err) => err.fmt(f),
                 Error::HttpError(err) => err.fmt(f),
                 Error::TemplateError(err) => err.fmt(f),
+                Error::JsonError(err) => err.fmt(f),
             }
         }
     }
@@ -1,146 +1,180 @@
 // Adapted from https://www.mschoeffler.de/2018/01/05/arduino-tutorial-how-to-use-the-rdm630-rdm6300-rfid-reader/
-use std::{io, str};
+use std::{fmt, io, str};
 
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 
 use futures::{
     future::FutureExt, // for `.fuse()`
 
     select,
-    stream::StreamExt,
+    stream::{Stream, StreamExt},
 };
 
 use tokio::sync::watch;
 use tokio::task;
+use tokio::time::{delay_for, Duration, Instant};
 use tokio_util::codec::{Decoder, Encoder};
 
-use bytes::buf::Buf;
-use bytes::BytesMut;
+use bytes::{Buf, BytesMut};
 
+use crate::config::{Parity, ReaderModel, Rfid};
+use crate::feeder::Device;
 use crate::state::{Action, Event, EventTx};
+use crate::tag::TagId;
+use crate::utils::wait_until;
 
-const DEFAULT_TTY: &str = "/dev/ttyS0";
+mod fdx_b;
+use fdx_b::FdxBCodec;
 
+mod presence;
+use presence::TagPresence;
+
+mod wiegand;
+
+/// Time to wait before trying to open the serial port again
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Why reading tags stopped
+enum ReadEnd {
+    Shutdown,
+    Disconnected,
+}
+
+/// A frame that couldn't be decoded. These are expected on a noisy line and
+/// the codec resynchronizes on the next start byte.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// Bytes that weren't part of a frame were dropped
+    Garbage(BytesMut),
+    /// A frame without an end byte
+    Framing,
+    /// The frame checksum didn't match its data
+    Checksum,
+    /// The tag data wasn't hex digits
+    InvalidDigits(String),
+    /// A Wiegand frame with an unsupported number of bits
+    FrameLength(usize),
+    /// A Wiegand frame failed its parity check
+    Parity,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Garbage(garbage) => write!(f, "Garbage before start byte {:?}", garbage),
+            DecodeError::Framing => write!(f, "Frame missing end byte"),
+            DecodeError::Checksum => write!(f, "Checksum missmatch"),
+            DecodeError::InvalidDigits(digits) => write!(f, "Invalid tag digits {:?}", digits),
+            DecodeError::FrameLength(bits) => write!(f, "Unsupported {} bit frame", bits),
+            DecodeError::Parity => write!(f, "Parity error"),
+        }
+    }
+}
+
+/// Decodes frames from EM4100 readers such as the RDM6300
 struct RFIDCodec;
 
 impl Decoder for RFIDCodec {
-    type Item = u32;
+    type Item = Result<TagId, DecodeError>;
     type Error = io::Error;
 
     ///
     /// |02| 10 bytes | 2 byte checksum |03| => 14 bytes
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        // Read until we hit a 2.
-        // Return eat the bytes up to the 2 and error if there is data before a 2
+        // Eat the bytes up to the 2 and report them if there is data before a 2
         // If the first byte is a 2, read upto 14 bytes.
         // If there are fewer then 14 bytes return Ok(None)
+        // Make sure the last one is a 3. If not drop the 2 and look for the next one
         // Consume these 14 bytes.
-        // Make sure the last one is a 3 and return error if it's not
-        // Validate the check sum and return an error if it's not
+        // Validate the check sum and report it if it's not
         // Return bytes 1..+11 as the rfid number
 
-        let test_src = src.as_ref();
+        if let Some(garbage) = skip_to_start(src) {
+            return Ok(Some(Err(DecodeError::Garbage(garbage))));
+        }
+
         // Need at least 14 bytes to do work
-        if test_src.len() < 14 {
+        if src.len() < 14 {
             return Ok(None);
         }
 
-        let start_pos = find_byte(test_src, 2);
-
-        if let Some(0) = start_pos {
-            // The last byte should be a 3. If not we have garbage on the line
-            if test_src[13] != 3 {
-                // Consume the first byte then upto the next byte of value 2.
-                let _ = src.get_u8();
-                return Err(consume_bytes(src, find_byte(src.as_ref(), 2)));
-            }
+        // The last byte should be a 3. If not we have garbage on the line
+        if src[13] != 3 {
+            skip_bad_frame(src);
+            return Ok(Some(Err(DecodeError::Framing)));
+        }
 
-            // We have a valid frame. Eat the frame.
-            let line = src.split_to(14);
-            let my_slice: &[u8] = line.as_ref();
-
-            let msg_data: &[u8] = &my_slice[1..11];
-            let _version_data: &[u8] = &my_slice[1..3];
-            let tag_data: &[u8] = &my_slice[3..11];
-            let checksum_data: &[u8] = &my_slice[11..13];
-
-            let checksum_str = checksum_data
-                .into_iter()
-                .map(|c| char::from(*c))
-                .collect::<String>();
-
-            let checksum = u16::from_str_radix(&checksum_str, 16).unwrap_or(0);
-
-            trace!(
-                "checksum: {}, checksum3: {}{}, slice: {:x?}",
-                checksum,
-                char::from(checksum_data[0]),
-                char::from(checksum_data[1]),
-                my_slice
-            );
-
-            let computed_checksum = compute_checksum(msg_data).unwrap();
-            trace!(
-                "Computed checksum {}, HEX: {:X?}",
-                computed_checksum,
-                computed_checksum
-            );
-
-            if checksum != computed_checksum {
-                return Err(io::Error::new(io::ErrorKind::Other, "Checksum missmatch"));
+        // We have a valid frame. Eat the frame.
+        let line = src.split_to(14);
+        let my_slice: &[u8] = line.as_ref();
+
+        // 5 data bytes then the checksum byte, each as 2 hex digits
+        let frame_data: &[u8] = &my_slice[1..13];
+        let frame_bytes = match hex_bytes(frame_data) {
+            Some(bytes) => bytes,
+            None => {
+                let digits = frame_data.iter().map(|c| char::from(*c)).collect();
+                return Ok(Some(Err(DecodeError::InvalidDigits(digits))));
             }
+        };
+        let msg_data: &[u8] = &frame_bytes[0..5];
+        let checksum = frame_bytes[5];
 
-            let tag_str = tag_data
-                .into_iter()
-                .map(|c| char::from(*c))
-                .collect::<String>();
+        trace!("checksum: {:X}, slice: {:x?}", checksum, my_slice);
 
-            let tag = u32::from_str_radix(&tag_str, 16).unwrap_or(0);
-            trace!("Tag: {:?} tag_str: {:?}", tag, tag_str);
+        let computed_checksum = compute_checksum(msg_data);
+        trace!("Computed checksum {:X}", computed_checksum);
 
-            return Ok(Some(tag));
-        } else {
-            // The first byte wasn't a 2. Consume the buffer upto the 2 we found
-            return Err(consume_bytes(src, start_pos));
+        if checksum != computed_checksum {
+            return Ok(Some(Err(DecodeError::Checksum)));
         }
+
+        // The version byte is kept as it's part of the id on the card
+        let version = msg_data[0];
+        let card = u32::from_be_bytes([msg_data[1], msg_data[2], msg_data[3], msg_data[4]]);
+        trace!("Version: {:?} card: {:?}", version, card);
+
+        Ok(Some(Ok(TagId::Em4100 { version, card })))
     }
 }
 
-/// Computes the checksum from the given bytes. Must be length 10
-fn compute_checksum(s: &[u8]) -> Option<u16> {
-    if s.len() != 10 {
-        return None;
-    }
+/// Computes the checksum from the given bytes
+fn compute_checksum(s: &[u8]) -> u8 {
+    s.iter().fold(0, |acc, b| acc ^ b)
+}
 
-    let mut acc: u16 = 0;
-    for i in 0..5 {
-        let i = i * 2;
-        let value = &s[i..(i + 2)];
-        if let Ok(number_string) = str::from_utf8(value) {
-            acc = acc ^ u16::from_str_radix(number_string, 16).unwrap_or(0)
-        }
-    }
-    Some(acc)
+/// Parse pairs of hex digits into bytes. Returns None if any aren't hex digits.
+fn hex_bytes(digits: &[u8]) -> Option<Vec<u8>> {
+    digits
+        .chunks(2)
+        .map(|pair| {
+            str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect()
 }
 
 fn find_byte(buf: &[u8], byte: u8) -> Option<usize> {
     buf.as_ref().iter().position(|b| *b == byte)
 }
 
-fn consume_bytes(src: &mut BytesMut, count: Option<usize>) -> io::Error {
-    match count {
-        Some(n) => {
-            let garbage: BytesMut = src.split_to(n);
+/// Drop any bytes before the start of a frame. Returns the dropped bytes if there were any.
+fn skip_to_start(src: &mut BytesMut) -> Option<BytesMut> {
+    match find_byte(src.as_ref(), 2) {
+        Some(0) => None,
+        Some(n) => Some(src.split_to(n)),
+        None if src.is_empty() => None,
+        None => Some(src.split()),
+    }
+}
 
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Garbage before start byte {:?}", garbage),
-            )
-        } // Consume up to X and return an error
-        None => {
-            src.clear();
-            io::Error::new(io::ErrorKind::Other, "All bytes in buffer are bad")
-        }
+/// Drop a frame that was missing its end byte, up to the next start byte, so
+/// its bytes aren't reported again as garbage.
+fn skip_bad_frame(src: &mut BytesMut) {
+    match find_byte(&src[1..], 2) {
+        Some(n) => src.advance(n + 1),
+        None => src.clear(),
     }
 }
 
@@ -153,41 +187,160 @@ impl Encoder for RFIDCodec {
     }
 }
 
-pub fn rfid_reader(tx: EventTx, mut stop_rx: watch::Receiver<Action>) -> task::JoinHandle<()> {
+pub fn rfid_reader(
+    tx: EventTx,
+    mut stop_rx: watch::Receiver<Action>,
+    rfid_config: Rfid,
+) -> task::JoinHandle<()> {
     task::spawn(async move {
         debug!("starting rfid reader");
-        // Default settings look to be okay
-        let settings = tokio_serial::SerialPortSettings::default();
-
-        //settings.timeout = std::time::Duration::from_secs(190);
+        let mut presence = TagPresence::new(Duration::from_millis(rfid_config.absence_timeout));
 
-        let mut port = tokio_serial::Serial::from_path(DEFAULT_TTY, &settings).unwrap();
-
-        port.set_exclusive(false)
-            .expect("Unable to set serial port exclusive to false");
+        if let ReaderModel::Wiegand = rfid_config.reader {
+            match rfid_config.wiegand.as_ref() {
+                Some(wiegand_config) => {
+                    wiegand::read_tags(wiegand_config, &mut presence, &tx, &mut stop_rx).await
+                }
+                None => error!("The Wiegand reader needs the [rfid.wiegand] pins configured"),
+            }
+            debug!("exiting");
+            return;
+        }
 
-        let mut reader = RFIDCodec.framed(port);
-        //pin_mut!(reader);
+        // Fault once for each run of failed opens, so it can be cleared
+        let mut faulted = false;
         loop {
-            select! {
-                some_id = reader.next().fuse() => {
-                    match some_id {
-                        Some(line) => {
-                            let line = line.expect("Failed to read");
-                            if let Err(err) = tx.send(Event::ReadTag(line)) {
-                                error!("Error updating last read tag: {}", err);
-                            }
-                    info!("{}", line)
+            match open_port(&rfid_config) {
+                Ok(port) => {
+                    if faulted {
+                        faulted = false;
+                        info!("RFID reader {} opened again", rfid_config.device);
+                        if let Err(err) = tx.send(Event::Recovered(Device::RfidReader)) {
+                            error!("Error sending recovery: {}", err);
                         }
-                        None => ()
                     }
+                    let reader = match rfid_config.reader {
+                        ReaderModel::Em4100 => RFIDCodec.framed(port).left_stream(),
+                        ReaderModel::FdxB => FdxBCodec.framed(port).right_stream(),
+                        ReaderModel::Wiegand => unreachable!("Wiegand readers aren't serial"),
+                    };
+                    let end = read_tags(reader, &mut presence, &tx, &mut stop_rx).await;
+                    if let ReadEnd::Shutdown = end {
+                        break;
+                    }
+                    warn!("RFID reader {} disconnected", rfid_config.device);
                 }
-                event = stop_rx.recv().fuse() => if let Some(Action::Shutdown) = event {
-                    debug!("Ending RFID task");
-                    break
+                Err(err) => {
+                    error!("Unable to open RFID reader {}: {}", rfid_config.device, err);
+                    if !faulted {
+                        faulted = true;
+                        let reason = format!("RFID reader {} won't open", rfid_config.device);
+                        if let Err(err) = tx.send(Event::Fault(Device::RfidReader, reason)) {
+                            error!("Error sending fault: {}", err);
+                        }
+                    }
                 }
             }
+
+            if !wait_to_retry(&mut stop_rx).await {
+                break;
+            }
         }
         debug!("exiting");
     })
 }
+
+fn open_port(rfid_config: &Rfid) -> io::Result<tokio_serial::Serial> {
+    let settings = tokio_serial::SerialPortSettings {
+        baud_rate: rfid_config.baud_rate,
+        parity: match rfid_config.parity {
+            Parity::None => tokio_serial::Parity::None,
+            Parity::Odd => tokio_serial::Parity::Odd,
+            Parity::Even => tokio_serial::Parity::Even,
+        },
+        ..tokio_serial::SerialPortSettings::default()
+    };
+
+    let mut port = tokio_serial::Serial::from_path(&rfid_config.device, &settings)?;
+
+    if let Err(err) = port.set_exclusive(false) {
+        warn!("Unable to set serial port exclusive to false: {}", err);
+    }
+
+    Ok(port)
+}
+
+/// Wait before opening the port again. Returns false if asked to shut down.
+async fn wait_to_retry(stop_rx: &mut watch::Receiver<Action>) -> bool {
+    let mut delay = delay_for(RETRY_DELAY).fuse();
+    loop {
+        select! {
+            _ = delay => return true,
+            event = stop_rx.recv().fuse() => if let Some(Action::Shutdown) = event {
+                debug!("Ending RFID task");
+                return false
+            }
+        }
+    }
+}
+
+/// Send each tag decoded from the port until shut down or the port closes
+async fn read_tags<S>(
+    mut reader: S,
+    presence: &mut TagPresence,
+    tx: &EventTx,
+    stop_rx: &mut watch::Receiver<Action>,
+) -> ReadEnd
+where
+    S: Stream<Item = Result<Result<TagId, DecodeError>, io::Error>> + Unpin,
+{
+    loop {
+        select! {
+            some_id = reader.next().fuse() => {
+                match some_id {
+                    Some(Ok(Ok(line))) => send_tag(line, presence, tx),
+                    Some(Ok(Err(decode_err))) => send_decode_error(decode_err, tx),
+                    Some(Err(err)) => {
+                        error!("Error reading RFID reader: {}", err);
+                        return ReadEnd::Disconnected;
+                    }
+                    None => return ReadEnd::Disconnected,
+                }
+            }
+            _ = wait_until(presence.deadline()).fuse() => send_departure(presence, tx),
+            event = stop_rx.recv().fuse() => if let Some(Action::Shutdown) = event {
+                debug!("Ending RFID task");
+                return ReadEnd::Shutdown
+            }
+        }
+    }
+}
+
+/// Send a tag that was read along with any change in tag presence
+fn send_tag(tag: TagId, presence: &mut TagPresence, tx: &EventTx) {
+    if let Err(err) = tx.send(Event::ReadTag(tag)) {
+        error!("Error updating last read tag: {}", err);
+    }
+    for event in presence.seen(tag, Instant::now()) {
+        if let Err(err) = tx.send(event) {
+            error!("Error sending tag presence: {}", err);
+        }
+    }
+    info!("{}", tag)
+}
+
+/// Send the departure of the present tag if it's gone
+fn send_departure(presence: &mut TagPresence, tx: &EventTx) {
+    if let Some(event) = presence.expire(Instant::now()) {
+        if let Err(err) = tx.send(event) {
+            error!("Error sending tag presence: {}", err);
+        }
+    }
+}
+
+fn send_decode_error(decode_err: DecodeError, tx: &EventTx) {
+    warn!("Unable to decode RFID frame: {}", decode_err);
+    if let Err(err) = tx.send(Event::TagDecodeError) {
+        error!("Error counting decode error: {}", err);
+    }
+}
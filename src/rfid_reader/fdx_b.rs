@@ -0,0 +1,150 @@
+// Frame format from the WL-134 datasheet. Other FDX-B serial modules use the same framing.
+use std::io;
+
+use log::trace;
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{skip_bad_frame, skip_to_start, DecodeError};
+use crate::tag::TagId;
+
+const FRAME_LEN: usize = 30;
+
+/// Decodes frames from FDX-B (ISO 11784/11785) readers
+pub struct FdxBCodec;
+
+impl Decoder for FdxBCodec {
+    type Item = Result<TagId, DecodeError>;
+    type Error = io::Error;
+
+    ///
+    /// |02| 10 byte id | 4 byte country | 1 byte data flag | 1 byte animal flag |
+    /// 10 bytes reserved | 1 byte checksum | 1 byte inverted checksum |03| => 30 bytes
+    ///
+    /// The id and country are hex digits with the least significant digit first.
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(garbage) = skip_to_start(src) {
+            return Ok(Some(Err(DecodeError::Garbage(garbage))));
+        }
+
+        if src.len() < FRAME_LEN {
+            return Ok(None);
+        }
+
+        // The last byte should be a 3. If not we have garbage on the line
+        if src[FRAME_LEN - 1] != 3 {
+            skip_bad_frame(src);
+            return Ok(Some(Err(DecodeError::Framing)));
+        }
+
+        // We have a valid frame. Eat the frame.
+        let frame = src.split_to(FRAME_LEN);
+        trace!("FDX-B frame: {:x?}", frame.as_ref());
+
+        // The checksum is the xor of the 26 data bytes
+        let checksum = frame[1..27].iter().fold(0, |acc, b| acc ^ b);
+        if frame[27] != checksum || frame[28] != !checksum {
+            return Ok(Some(Err(DecodeError::Checksum)));
+        }
+
+        let tag = parse_reversed_hex(&frame[1..11]).and_then(|national_id| {
+            let country = parse_reversed_hex(&frame[11..15])?;
+            trace!("Country: {} national id: {}", country, national_id);
+            Ok(TagId::FdxB {
+                country: country as u16,
+                national_id,
+            })
+        });
+
+        Ok(Some(tag))
+    }
+}
+
+/// Parse hex digits sent least significant digit first
+fn parse_reversed_hex(digits: &[u8]) -> Result<u64, DecodeError> {
+    let hex = digits.iter().rev().map(|c| char::from(*c)).collect::<String>();
+    u64::from_str_radix(&hex, 16).map_err(|_err| DecodeError::InvalidDigits(hex))
+}
+
+impl Encoder for FdxBCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn encode(&mut self, _item: Self::Item, _dst: &mut BytesMut) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Country 999 and national id 123456789012 with the data flag set
+    const FRAME: &[u8; FRAME_LEN] = b"\x0241A199EBC17E30100000000000\x70\x8f\x03";
+
+    fn decode(src: &mut BytesMut) -> Option<Result<TagId, DecodeError>> {
+        FdxBCodec.decode(src).unwrap()
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut src = BytesMut::from(&FRAME[..]);
+        let tag = decode(&mut src).unwrap().unwrap();
+        assert_eq!(
+            tag,
+            TagId::FdxB {
+                country: 999,
+                national_id: 123_456_789_012
+            }
+        );
+        assert_eq!(tag.decimal(), "999123456789012");
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_partial_frame() {
+        let mut src = BytesMut::from(&FRAME[..20]);
+        assert!(decode(&mut src).is_none());
+        assert_eq!(src.len(), 20);
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut frame = FRAME.to_vec();
+        frame[27] ^= 1;
+        let mut src = BytesMut::from(&frame[..]);
+        assert!(matches!(decode(&mut src), Some(Err(DecodeError::Checksum))));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_resync_after_garbage() {
+        let mut src = BytesMut::from(&b"\x31\x32"[..]);
+        src.extend_from_slice(FRAME);
+        match decode(&mut src) {
+            Some(Err(DecodeError::Garbage(garbage))) => assert_eq!(&garbage[..], b"12"),
+            _ => panic!("Expected the garbage to be reported"),
+        }
+        assert!(matches!(decode(&mut src), Some(Ok(TagId::FdxB { .. }))));
+    }
+
+    #[test]
+    fn test_resync_after_missing_end_byte() {
+        // A frame cut short by the start of the next one
+        let mut src = BytesMut::from(&FRAME[..10]);
+        src.extend_from_slice(FRAME);
+        assert!(matches!(decode(&mut src), Some(Err(DecodeError::Framing))));
+        assert!(matches!(decode(&mut src), Some(Ok(TagId::FdxB { .. }))));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_parse_reversed_hex() {
+        assert_eq!(parse_reversed_hex(b"7E30").unwrap(), 999);
+        assert!(matches!(
+            parse_reversed_hex(b"7G30"),
+            Err(DecodeError::InvalidDigits(_))
+        ));
+    }
+}
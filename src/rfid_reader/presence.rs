@@ -0,0 +1,71 @@
+use tokio::time::{Duration, Instant};
+
+use crate::state::Event;
+use crate::tag::TagId;
+
+/// Turns the repeated reads of a tag sitting in the reader's field into
+/// arrival and departure events.
+pub struct TagPresence {
+    /// How long a tag can go unread before it has left
+    timeout: Duration,
+    present: Option<Present>,
+}
+
+struct Present {
+    tag: TagId,
+    arrived: Instant,
+    last_seen: Instant,
+}
+
+impl TagPresence {
+    pub fn new(timeout: Duration) -> Self {
+        TagPresence {
+            timeout,
+            present: None,
+        }
+    }
+
+    /// Record a read of the tag. A different tag replaces the one that was present.
+    pub fn seen(&mut self, tag: TagId, now: Instant) -> Vec<Event> {
+        match self.present.as_mut() {
+            Some(present) if present.tag == tag => {
+                present.last_seen = now;
+                vec![]
+            }
+            _ => {
+                let mut events = vec![];
+                if let Some(event) = self.depart(now) {
+                    events.push(event);
+                }
+                self.present = Some(Present {
+                    tag,
+                    arrived: now,
+                    last_seen: now,
+                });
+                events.push(Event::TagArrived(tag));
+                events
+            }
+        }
+    }
+
+    /// When the present tag will be considered gone
+    pub fn deadline(&self) -> Option<Instant> {
+        self.present
+            .as_ref()
+            .map(|present| present.last_seen + self.timeout)
+    }
+
+    /// Depart the present tag if it hasn't been seen within the timeout
+    pub fn expire(&mut self, now: Instant) -> Option<Event> {
+        match self.deadline() {
+            Some(deadline) if deadline <= now => self.depart(now),
+            _ => None,
+        }
+    }
+
+    fn depart(&mut self, now: Instant) -> Option<Event> {
+        self.present
+            .take()
+            .map(|present| Event::TagDeparted(present.tag, now - present.arrived))
+    }
+}
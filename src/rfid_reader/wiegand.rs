@@ -0,0 +1,172 @@
+// Wiegand readers pulse D0 low for a 0 bit and D1 low for a 1 bit. A frame
+// ends when no bits arrive for the frame timeout.
+use std::time;
+
+use futures::{
+    future::FutureExt, // for `.fuse()`
+    select,
+};
+use log::{debug, error};
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+
+use super::presence::TagPresence;
+use super::{send_decode_error, send_departure, send_tag, DecodeError};
+use crate::config::Wiegand;
+use crate::state::{Action, ActionRx, EventTx};
+use crate::tag::TagId;
+use crate::utils::{self, wait_until};
+
+/// Edges closer together than this are noise rather than bits
+const MIN_BIT_INTERVAL: Duration = Duration::from_micros(200);
+
+/// Read tags from the data lines until shut down
+pub async fn read_tags(
+    wiegand_config: &Wiegand,
+    presence: &mut TagPresence,
+    tx: &EventTx,
+    stop_rx: &mut ActionRx,
+) {
+    let (d0, d1) = match (open_pin(wiegand_config.d0_pin), open_pin(wiegand_config.d1_pin)) {
+        (Some(d0), Some(d1)) => (d0, d1),
+        _ => return,
+    };
+
+    let (bit_tx, mut bit_rx) = mpsc::unbounded_channel::<(bool, time::Instant)>();
+    let d1_tx = bit_tx.clone();
+    let _d0_watcher = utils::watch_pin(d0, Trigger::FallingEdge, stop_rx.clone(), move |_| {
+        let _ = bit_tx.send((false, time::Instant::now()));
+    });
+    let _d1_watcher = utils::watch_pin(d1, Trigger::FallingEdge, stop_rx.clone(), move |_| {
+        let _ = d1_tx.send((true, time::Instant::now()));
+    });
+
+    let frame_timeout = Duration::from_millis(wiegand_config.frame_timeout);
+    let mut bits: Vec<bool> = vec![];
+    let mut last_bit: Option<Instant> = None;
+    loop {
+        select! {
+            bit = bit_rx.recv().fuse() => {
+                let (bit, at) = match bit {
+                    Some(bit) => bit,
+                    // The pin watchers have stopped
+                    None => break,
+                };
+                let at = Instant::from_std(at);
+                match last_bit {
+                    Some(last) if at - last < MIN_BIT_INTERVAL => debug!("Ignoring Wiegand glitch"),
+                    _ => {
+                        bits.push(bit);
+                        last_bit = Some(at);
+                    }
+                }
+            }
+            _ = wait_until(last_bit.map(|last| last + frame_timeout)).fuse() => {
+                match decode_frame(&bits) {
+                    Ok(tag) => send_tag(tag, presence, tx),
+                    Err(decode_err) => send_decode_error(decode_err, tx),
+                }
+                bits.clear();
+                last_bit = None;
+            }
+            _ = wait_until(presence.deadline()).fuse() => send_departure(presence, tx),
+            event = stop_rx.recv().fuse() => if let Some(Action::Shutdown) = event {
+                debug!("Ending RFID task");
+                break
+            }
+        }
+    }
+}
+
+fn open_pin(pin: u8) -> Option<InputPin> {
+    match Gpio::new().and_then(|gpios| gpios.get(pin)) {
+        // The data lines idle high
+        Ok(pin) => Some(pin.into_input_pullup()),
+        Err(err) => {
+            error!("Unable to open Wiegand pin {}: {}", pin, err);
+            None
+        }
+    }
+}
+
+/// Decode a Wiegand-26 or Wiegand-34 frame. The first bit is even parity over
+/// the first half of the data and the last bit is odd parity over the second half.
+fn decode_frame(bits: &[bool]) -> Result<TagId, DecodeError> {
+    if bits.len() != 26 && bits.len() != 34 {
+        return Err(DecodeError::FrameLength(bits.len()));
+    }
+
+    let half = bits.len() / 2;
+    let ones = |bits: &[bool]| bits.iter().filter(|bit| **bit).count();
+    if ones(&bits[..half]) % 2 != 0 || ones(&bits[half..]) % 2 != 1 {
+        return Err(DecodeError::Parity);
+    }
+
+    let data_bits = &bits[1..bits.len() - 1];
+    let data = data_bits
+        .iter()
+        .fold(0u32, |acc, bit| (acc << 1) | *bit as u32);
+
+    Ok(TagId::Wiegand {
+        bits: bits.len() as u8,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(bits: &str) -> Vec<bool> {
+        bits.chars().map(|bit| bit == '1').collect()
+    }
+
+    #[test]
+    fn test_decode_wiegand_26() {
+        let tag = decode_frame(&frame("01000001101111010000110111")).unwrap();
+        assert_eq!(
+            tag,
+            TagId::Wiegand {
+                bits: 26,
+                data: (131 << 16) | 31259
+            }
+        );
+        assert_eq!(tag.facility_card().as_deref(), Some("131,31259"));
+    }
+
+    #[test]
+    fn test_decode_wiegand_34() {
+        let tag = decode_frame(&frame("1000100100011010001010110011110001")).unwrap();
+        assert_eq!(
+            tag,
+            TagId::Wiegand {
+                bits: 34,
+                data: 0x1234_5678
+            }
+        );
+    }
+
+    #[test]
+    fn test_parity_error() {
+        // Each half with one data bit flipped
+        for bits in &["01100001101111010000110111", "01000001101111010000010111"] {
+            assert!(matches!(
+                decode_frame(&frame(bits)),
+                Err(DecodeError::Parity)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_frame_length() {
+        assert!(matches!(
+            decode_frame(&frame("0100000110111101000011011")),
+            Err(DecodeError::FrameLength(25))
+        ));
+        assert!(matches!(
+            decode_frame(&[]),
+            Err(DecodeError::FrameLength(0))
+        ));
+    }
+}
@@ -1,9 +1,31 @@
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use log::{debug, error};
+use chrono::{DateTime, Local};
+use futures::{
+    future::FutureExt, // for `.fuse()`
+    select,
+};
+use log::{debug, error, info};
 
 use tokio::sync::{mpsc, watch, Mutex};
 use tokio::task;
+use tokio::time;
+
+use crate::analog::Reading;
+use crate::calibration::{Calibration, CalibrationPoint};
+use crate::capture_policy::{CapturePolicy, Trigger};
+use crate::config::{self, Config, Distance, PresenceSensor};
+use crate::distance::{self, Thresholds, ThresholdsTx};
+use crate::feeder::{Device, Feeder, Input};
+use crate::history::DistanceHistory;
+use crate::hopper::Hopper;
+use crate::lid::{LidCommand, LidTx};
+use crate::load_cell::{ScaleCommand, ScaleTx};
+use crate::notifier::{Notification, NotificationTx};
+use crate::tag::TagId;
+use crate::utils::wait_until;
 
 /// Shorthand for the transmit half of the event message channel.
 pub type EventTx = mpsc::UnboundedSender<Event>;
@@ -17,35 +39,264 @@ pub type ActionTx = watch::Sender<Action>;
 /// Shorthand for the recieve half of the broadcast channel.
 pub type ActionRx = watch::Receiver<Action>;
 
+/// Shorthand for the send half of the enrollment mode channel.
+pub type EnrollmentTx = watch::Sender<bool>;
+
+/// Shorthand for the recieve half of the enrollment mode channel.
+pub type EnrollmentRx = watch::Receiver<bool>;
+
+/// Where the reducer sends its responses. A broadcast action can be replaced
+/// by the next one before a task sees it, so anything else goes on a channel of
+/// its own.
+pub struct Senders {
+    pub actions: ActionTx,
+    /// Whether enrollment mode is on, when there is an LED to show it
+    pub enrollment: Option<EnrollmentTx>,
+    /// The distance thresholds, which change with the calibration
+    pub thresholds: ThresholdsTx,
+    pub notifications: NotificationTx,
+    /// Commands for the scale, when there is a load cell
+    pub scale: Option<ScaleTx>,
+    /// Commands for the lid, when there is one
+    pub lid: Option<LidTx>,
+}
+
+/// Number of visits to remember
+const MAX_VISITS: usize = 50;
+
+/// An image captured by one of the cameras
+pub struct Picture {
+    /// Unique id of the picture. Unlike the index this doesn't change as
+    /// pictures are removed.
+    pub id: usize,
+    /// Index of the camera the image came from
+    pub camera: u8,
+    pub image: Vec<u8>,
+}
+
+/// A cat visiting the feeder, from entering the distance threshold until
+/// leaving it.
+pub struct Visit {
+    pub started: DateTime<Local>,
+    pub ended: Option<DateTime<Local>>,
+    /// Tag read during the visit
+    pub tag: Option<TagId>,
+    /// Ids of the pictures taken on entering
+    pub before: Vec<usize>,
+    /// Ids of the pictures taken on leaving
+    pub after: Vec<usize>,
+    /// Bowl weight in grams when the visit started
+    pub start_weight: Option<f32>,
+    /// Bowl weight in grams when the visit ended
+    pub end_weight: Option<f32>,
+}
+
+impl Visit {
+    /// Grams eaten during the visit, if the bowl was weighed at both ends
+    pub fn eaten(&self) -> Option<f32> {
+        match (self.start_weight, self.end_weight) {
+            (Some(start), Some(end)) => Some(start - end),
+            _ => None,
+        }
+    }
+}
+
 /// The state of the application
 pub struct State {
     pub click_count: u32,
     pub distance: u16,
-    in_threshold: bool,
-    last_tag_read: Option<u32>,
+    /// Last reading from each analog channel keyed by name
+    pub analog_readings: BTreeMap<String, Reading>,
+    last_tag_read: Option<TagId>,
+    /// Tag currently in the reader's field
+    pub present_tag: Option<TagId>,
+    /// Count of RFID frames that couldn't be decoded
+    pub tag_decode_errors: u32,
     pub loop_count: u32,
-    pub has_camera: bool,
+    /// Indexes of the cameras that were opened
+    pub cameras: Vec<u8>,
     taking_picture: bool,
-    pub pictures: Vec<Vec<u8>>,
+    /// What caused the pictures being taken, if it was a visit
+    capture_trigger: Option<Trigger>,
+    next_picture_id: usize,
+    pub pictures: Vec<Picture>,
+    pub visits: Vec<Visit>,
+    /// Names of the cats keyed by tag id
+    cat_names: HashMap<String, String>,
+    /// Tags the lid opens for
+    authorized_ids: Vec<String>,
+    /// Names of the cats added in enrollment mode keyed by tag id
+    enrolled_cats: HashMap<String, String>,
+    /// When enrollment mode gives up waiting for a tag
+    enrolling_until: Option<Instant>,
+    enrollment_timeout: Duration,
+    capture_policy: CapturePolicy,
+    distance_config: Distance,
+    /// Maps distance readings to centimeters
+    pub calibration: Calibration,
+    /// Readings recorded in calibration mode
+    pub calibration_readings: Option<Vec<CalibrationPoint>>,
+    pub distance_history: DistanceHistory,
+    /// Last weight of the bowl in grams
+    pub bowl_weight: Option<f32>,
+    /// Food left in the hopper, when there is a sensor for it
+    pub hopper: Option<Hopper>,
+    /// The last notification said the food was low
+    low_food_notified: bool,
+    pub feeder: Feeder,
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(
+        config: &Config,
+        enrolled_cats: HashMap<String, String>,
+        calibration: Calibration,
+        thresholds: Thresholds,
+    ) -> Self {
+        let rfid = &config.rfid;
         State {
             click_count: 0,
             distance: 0,
-            in_threshold: false,
+            analog_readings: BTreeMap::new(),
             last_tag_read: None,
+            present_tag: None,
+            tag_decode_errors: 0,
             loop_count: 0,
-            has_camera: false,
+            cameras: vec![],
             taking_picture: false,
+            capture_trigger: None,
+            next_picture_id: 0,
             pictures: vec![],
+            visits: vec![],
+            cat_names: rfid.names.clone(),
+            authorized_ids: rfid.valid_ids.clone(),
+            enrolled_cats,
+            enrolling_until: None,
+            enrollment_timeout: Duration::from_secs(rfid.enrollment_timeout),
+            capture_policy: CapturePolicy::new(config.captures.clone()),
+            distance_config: config.distance.clone(),
+            calibration,
+            calibration_readings: None,
+            distance_history: DistanceHistory::new(thresholds.enter, thresholds.exit),
+            bowl_weight: None,
+            hopper: config.hopper.clone().map(Hopper::new),
+            low_food_notified: false,
+            feeder: Feeder::new(&config.feeder),
         }
     }
 
-    pub fn last_tag_read(&self) -> Option<u32> {
+    pub fn last_tag_read(&self) -> Option<TagId> {
         self.last_tag_read
     }
+
+    /// Name of the cat with the given tag, if it has one.
+    pub fn cat_name(&self, tag: &TagId) -> Option<&str> {
+        self.cat_names
+            .iter()
+            .chain(self.enrolled_cats.iter())
+            .find(|(id, _)| tag.matches(id))
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Waiting for a tag to add to the cats
+    pub fn is_enrolling(&self) -> bool {
+        self.enrolling_until.is_some()
+    }
+
+    /// Add the tag to the enrolled cats with a placeholder name
+    fn enroll(&mut self, tag: TagId) {
+        if let Some(name) = self.cat_name(&tag) {
+            info!("Tag {} is already enrolled as {}", tag, name);
+            return;
+        }
+
+        let name = format!("New cat {}", self.enrolled_cats.len() + 1);
+        info!("Enrolled tag {} as {}", tag, name);
+        let _ = self.enrolled_cats.insert(tag.to_string(), name);
+        if let Err(err) = config::save_registry(&self.enrolled_cats) {
+            error!("Error saving enrolled cats: {}", err);
+        }
+    }
+
+    /// Use the recorded readings as the new calibration and save them. Returns
+    /// true if there were enough readings.
+    fn save_calibration(&mut self) -> bool {
+        let points = match self.calibration_readings.take() {
+            Some(points) if points.len() >= 2 => points,
+            Some(points) => {
+                error!("Calibration needs at least two readings");
+                self.calibration_readings = Some(points);
+                return false;
+            }
+            None => return false,
+        };
+        info!("Saving calibration with {} readings", points.len());
+        if let Err(err) = config::save_calibration(&points) {
+            error!("Error saving calibration: {}", err);
+        }
+        self.calibration = Calibration::new(points);
+        true
+    }
+
+    /// Is the tag one the lid opens for
+    fn is_authorized(&self, tag: &TagId) -> bool {
+        self.authorized_ids.iter().any(|id| tag.matches(id))
+    }
+
+    pub fn has_camera(&self) -> bool {
+        !self.cameras.is_empty()
+    }
+
+    /// Current index of the picture with the given id
+    pub fn picture_index(&self, id: usize) -> Option<usize> {
+        self.pictures.iter().position(|picture| picture.id == id)
+    }
+
+    /// The last distance in centimeters. The PIR sensor only sees motion.
+    pub fn distance_cm(&self) -> Option<f32> {
+        match self.distance_config.sensor {
+            PresenceSensor::SharpIr => self.calibration.to_cm(self.distance),
+            PresenceSensor::Ultrasonic => Some(self.distance as f32 / 10.0),
+            PresenceSensor::Pir => None,
+        }
+    }
+
+    fn set_distance(&mut self, distance: u16) {
+        self.distance = distance;
+        self.distance_history.record(distance);
+    }
+
+    fn start_visit(&mut self) {
+        if self.visits.len() >= MAX_VISITS {
+            let _ = self.visits.remove(0);
+        }
+        self.visits.push(Visit {
+            started: Local::now(),
+            ended: None,
+            // The tag may have been read as the cat approached
+            tag: self.present_tag,
+            before: vec![],
+            after: vec![],
+            start_weight: self.bowl_weight,
+            end_weight: None,
+        });
+    }
+
+    fn end_visit(&mut self) {
+        let bowl_weight = self.bowl_weight;
+        let visit = match self.visits.last_mut() {
+            Some(visit) if visit.ended.is_none() => visit,
+            _ => return,
+        };
+        visit.ended = Some(Local::now());
+        visit.end_weight = bowl_weight;
+        if let Some(eaten) = visit.eaten() {
+            match visit.tag {
+                Some(tag) => info!("Tag {} ate {:.1} grams", tag, eaten),
+                None => info!("An unknown cat ate {:.1} grams", eaten),
+            }
+        }
+    }
 }
 
 /// Events that happen from the outside world. These are items that would
@@ -56,13 +307,25 @@ pub enum Event {
     /// Increment the Loop Accumliator
     IncLoop,
     /// Last Tag to be read
-    ReadTag(u32),
-    /// Register a Camera or Not
-    HasCamera(bool),
-    /// Add an image to the list of imasges
-    AddImage(Vec<u8>),
-    /// External request to take an image with the camera
-    TakeImageRequest,
+    ReadTag(TagId),
+    /// A tag came into the reader's field
+    TagArrived(TagId),
+    /// A tag left the reader's field after being present for the duration
+    TagDeparted(TagId, Duration),
+    /// An RFID frame couldn't be decoded
+    TagDecodeError,
+    /// Add the next tag read to the cats
+    StartEnrollment,
+    /// Stop waiting for a tag to enroll
+    CancelEnrollment,
+    /// Register the cameras that are available
+    HasCameras(Vec<u8>),
+    /// Add an image from the given camera to the list of imasges
+    AddImage(u8, Vec<u8>),
+    /// All requested images have been captured
+    CaptureComplete,
+    /// External request to take an image with the given camera, or all cameras
+    TakeImageRequest(Option<u8>),
     /// Request an image be deleted from the image list
     DeleteImage(usize),
     /// Endering the configured distance threshold
@@ -71,6 +334,34 @@ pub enum Event {
     Distance(u16),
     /// Exiting the configured distance threshold
     ExitDistanceThreshold(u16),
+    /// A reading from one of the analog channels
+    AnalogReading(Reading),
+    /// Weight of the bowl in grams
+    BowlWeight(f32),
+    /// Zero the scale with the bowl as it is
+    TareScale,
+    /// Calibrate the scale with the given grams in the bowl
+    CalibrateScale(f32),
+    /// The device has failed, with the reason
+    Fault(Device, String),
+    /// The device is working again
+    Recovered(Device),
+    /// The fault has been dealt with
+    ClearFault,
+    /// Suspend automatic captures and notifications while servicing the feeder
+    StartMaintenance,
+    /// Resume after servicing the feeder
+    EndMaintenance,
+    /// Start maintenance mode, or end it if it's on
+    ToggleMaintenance,
+    /// Start recording distance readings at known distances
+    StartCalibration,
+    /// Record the current distance reading as being at the given centimeters
+    RecordCalibration(f32),
+    /// Save the recorded readings as the calibration
+    SaveCalibration,
+    /// Stop calibrating without saving
+    CancelCalibration,
     /// Event requesting everything shut down
     Shutdown,
 }
@@ -83,38 +374,89 @@ pub enum Event {
 pub enum Action {
     /// Default action when app is starting up
     Startup,
-    /// Action to captue an image with the camera
-    TakePicture,
+    /// Action to captue an image with the given camera, or all cameras
+    TakePicture(Option<u8>),
     /// Action to shut down all tasks
     Shutdown,
 }
 
 // Consumes the event along with a state.
 // Updates the state object and sends out actions to take.
-async fn reducer(event: Event, state: &Mutex<State>, action_tx: &ActionTx) {
+async fn reducer(event: Event, state: &Mutex<State>, senders: &Senders) {
     match event {
         Event::IncLoop => {
-            state.lock().await.loop_count += 1;
+            let mut state = state.lock().await;
+            state.loop_count += 1;
+            tick_feeder(&mut state, senders);
         }
         Event::IncClick => {
             state.lock().await.click_count += 1;
         }
-        Event::ReadTag(tag) => state.lock().await.last_tag_read = Some(tag),
-        Event::HasCamera(camera) => {
-            state.lock().await.has_camera = camera;
+        Event::ReadTag(tag) => {
+            let mut state = state.lock().await;
+            state.last_tag_read = Some(tag);
+            if state.is_enrolling() {
+                state.enroll(tag);
+                end_enrollment(&mut state, senders);
+            }
+            if state.feeder.cat_present() {
+                if let Some(visit) = state.visits.last_mut() {
+                    visit.tag = Some(tag);
+                }
+            }
+        }
+        Event::TagArrived(tag) => {
+            info!("Tag {} arrived", tag);
+            let mut state = state.lock().await;
+            state.present_tag = Some(tag);
+            if !state.is_authorized(&tag) {
+                send_lid(&state, LidCommand::CloseNow, senders);
+            } else if state.feeder.cat_present() {
+                send_lid(&state, LidCommand::Open, senders);
+            }
+        }
+        Event::TagDeparted(tag, duration) => {
+            info!("Tag {} departed after {:?}", tag, duration);
+            let mut state = state.lock().await;
+            if state.present_tag == Some(tag) {
+                state.present_tag = None;
+            }
+            if state.is_authorized(&tag) {
+                send_lid(&state, LidCommand::CloseAfterGrace, senders);
+            }
+        }
+        Event::TagDecodeError => {
+            state.lock().await.tag_decode_errors += 1;
+        }
+        Event::StartEnrollment => {
+            let mut state = state.lock().await;
+            info!("Entering enrollment mode. Waiting for a new tag");
+            state.enrolling_until = Some(Instant::now() + state.enrollment_timeout);
+            send_enrollment(true, senders);
+        }
+        Event::CancelEnrollment => {
+            let mut state = state.lock().await;
+            if state.is_enrolling() {
+                info!("Enrollment mode cancelled");
+                end_enrollment(&mut state, senders);
+            }
+        }
+        Event::HasCameras(cameras) => {
+            state.lock().await.cameras = cameras;
         }
-        Event::TakeImageRequest => {
+        Event::TakeImageRequest(camera) => {
             let mut state = state.lock().await;
-            if state.has_camera && !state.taking_picture {
+            if state.has_camera() && !state.taking_picture {
                 state.taking_picture = true;
-                if let Err(_err) = action_tx.broadcast(Action::TakePicture) {
+                state.capture_trigger = None;
+                if let Err(_err) = senders.actions.broadcast(Action::TakePicture(camera)) {
                     error!("Error sending take picture");
                 }
             } else {
                 debug!("Image Taking request with no camera");
             }
         }
-        Event::AddImage(image) => {
+        Event::AddImage(camera, image) => {
             debug!("Saving image to memory");
             let mut state = state.lock().await;
             let picture_count = state.pictures.len();
@@ -122,8 +464,20 @@ async fn reducer(event: Event, state: &Mutex<State>, action_tx: &ActionTx) {
                 let _ = state.pictures.remove(0);
             }
 
-            state.pictures.push(image);
-            state.taking_picture = false;
+            let id = state.next_picture_id;
+            state.next_picture_id += 1;
+            state.pictures.push(Picture { id, camera, image });
+
+            let trigger = state.capture_trigger;
+            if let (Some(trigger), Some(visit)) = (trigger, state.visits.last_mut()) {
+                match trigger {
+                    Trigger::Enter => visit.before.push(id),
+                    Trigger::Exit => visit.after.push(id),
+                }
+            }
+        }
+        Event::CaptureComplete => {
+            state.lock().await.taking_picture = false;
         }
         Event::DeleteImage(id) => {
             let mut state = state.lock().await;
@@ -133,52 +487,240 @@ async fn reducer(event: Event, state: &Mutex<State>, action_tx: &ActionTx) {
         }
         Event::EnterDistanceThreshold(distance) => {
             let mut state = state.lock().await;
-            state.distance = distance;
-            state.in_threshold = true;
-            if state.has_camera && !state.taking_picture {
+            state.set_distance(distance);
+            state.feeder.cat_arrived();
+            state.capture_policy.start_visit();
+            state.start_visit();
+            if state
+                .present_tag
+                .map_or(false, |tag| state.is_authorized(&tag))
+            {
+                send_lid(&state, LidCommand::Open, senders);
+            }
+            if state.has_camera()
+                && !state.taking_picture
+                && !state.feeder.in_maintenance()
+                && state
+                    .capture_policy
+                    .try_capture(Trigger::Enter, Instant::now())
+            {
                 state.taking_picture = true;
-                if let Err(_err) = action_tx.broadcast(Action::TakePicture) {
+                state.capture_trigger = Some(Trigger::Enter);
+                if let Err(_err) = senders.actions.broadcast(Action::TakePicture(None)) {
                     error!("Error sending take picture");
                 }
             }
         }
         Event::Distance(distance) => {
             let mut state = state.lock().await;
-            state.distance = distance;
+            state.set_distance(distance);
+            tick_feeder(&mut state, senders);
         }
         Event::ExitDistanceThreshold(distance) => {
             let mut state = state.lock().await;
-            state.distance = distance;
-            state.in_threshold = false;
-            if state.has_camera && !state.taking_picture {
+            state.set_distance(distance);
+            state.feeder.cat_left();
+            state.end_visit();
+            if state.has_camera()
+                && !state.taking_picture
+                && !state.feeder.in_maintenance()
+                && state
+                    .capture_policy
+                    .try_capture(Trigger::Exit, Instant::now())
+            {
                 state.taking_picture = true;
-                if let Err(_err) = action_tx.broadcast(Action::TakePicture) {
+                state.capture_trigger = Some(Trigger::Exit);
+                if let Err(_err) = senders.actions.broadcast(Action::TakePicture(None)) {
                     error!("Error sending take picture");
                 }
             }
         }
+        Event::AnalogReading(reading) => {
+            let mut state = state.lock().await;
+            let low_food = state
+                .hopper
+                .as_mut()
+                .filter(|hopper| hopper.channel() == reading.name)
+                .and_then(|hopper| hopper.reading(reading.value));
+            if let Some(low) = low_food {
+                if !state.feeder.in_maintenance() {
+                    send_low_food(&mut state, low, senders);
+                }
+            }
+            let _ = state.analog_readings.insert(reading.name.clone(), reading);
+        }
+        Event::BowlWeight(grams) => {
+            state.lock().await.bowl_weight = Some(grams);
+        }
+        Event::TareScale => send_scale(ScaleCommand::Tare, senders),
+        Event::CalibrateScale(grams) => send_scale(ScaleCommand::Calibrate(grams), senders),
+        Event::Fault(device, reason) => {
+            error!("Feeder fault: {}", reason);
+            let mut state = state.lock().await;
+            if device == Device::LoadCell {
+                // Don't weigh visits with the last weight before it failed
+                state.bowl_weight = None;
+            }
+            state.feeder.fault(device, reason);
+        }
+        Event::Recovered(device) => {
+            info!("{:?} recovered", device);
+            state.lock().await.feeder.recovered(device);
+        }
+        Event::ClearFault => {
+            state.lock().await.feeder.clear_fault();
+        }
+        Event::StartMaintenance => {
+            info!("Starting maintenance mode");
+            state.lock().await.feeder.start_maintenance();
+        }
+        Event::EndMaintenance => {
+            let mut state = state.lock().await;
+            if state.feeder.end_maintenance() {
+                maintenance_ended(&mut state, senders);
+            }
+        }
+        Event::ToggleMaintenance => {
+            let mut state = state.lock().await;
+            if state.feeder.end_maintenance() {
+                maintenance_ended(&mut state, senders);
+            } else {
+                info!("Starting maintenance mode");
+                state.feeder.start_maintenance();
+            }
+        }
+        Event::StartCalibration => {
+            info!("Starting distance calibration");
+            state.lock().await.calibration_readings = Some(vec![]);
+        }
+        Event::RecordCalibration(cm) => {
+            let mut state = state.lock().await;
+            let counts = state.distance;
+            if let Some(readings) = state.calibration_readings.as_mut() {
+                info!("Recorded {} counts at {} cm", counts, cm);
+                readings.push(CalibrationPoint { counts, cm });
+            }
+        }
+        Event::SaveCalibration => {
+            let mut state = state.lock().await;
+            if state.save_calibration() {
+                update_thresholds(&mut state, senders);
+            }
+        }
+        Event::CancelCalibration => {
+            info!("Distance calibration cancelled");
+            state.lock().await.calibration_readings = None;
+        }
         Event::Shutdown => {
-            if let Err(_err) = action_tx.broadcast(Action::Shutdown) {
+            if let Err(_err) = senders.actions.broadcast(Action::Shutdown) {
                 error!("Error shutting down");
             }
         }
     };
 }
 
+// Without the RFID reader tags can't be checked, so the lid isn't opened. It
+// can still close.
+fn send_lid(state: &State, command: LidCommand, senders: &Senders) {
+    if command == LidCommand::Open && state.feeder.has_fault(Device::RfidReader) {
+        info!("Not opening the lid while the RFID reader is down");
+        return;
+    }
+    if let Some(lid_tx) = &senders.lid {
+        if let Err(err) = lid_tx.send(command) {
+            error!("Error sending lid {:?}: {}", command, err);
+        }
+    }
+}
+
+// Thresholds in centimeters move with the calibration. Bad ones are only
+// logged, as the feeder is already running with the old ones.
+fn update_thresholds(state: &mut State, senders: &Senders) {
+    match distance::thresholds(&state.distance_config, &state.calibration) {
+        Ok(thresholds) => {
+            state.distance_history.enter_threshold = thresholds.enter;
+            state.distance_history.exit_threshold = thresholds.exit;
+            if let Err(_err) = senders.thresholds.broadcast(thresholds) {
+                error!("Error sending distance thresholds");
+            }
+        }
+        Err(err) => error!("Keeping the old distance thresholds. {}", err),
+    }
+}
+
+fn send_scale(command: ScaleCommand, senders: &Senders) {
+    match &senders.scale {
+        Some(scale_tx) => {
+            if let Err(err) = scale_tx.send(command) {
+                error!("Error sending scale {:?}: {}", command, err);
+            }
+        }
+        None => error!("There is no load cell to {:?}", command),
+    }
+}
+
+fn send_low_food(state: &mut State, low: bool, senders: &Senders) {
+    state.low_food_notified = low;
+    if let Err(err) = senders.notifications.send(Notification::LowFood(low)) {
+        error!("Error sending low food: {}", err);
+    }
+}
+
+fn tick_feeder(state: &mut State, senders: &Senders) {
+    if let Some(Input::EndMaintenance) = state.feeder.tick(Instant::now()) {
+        info!("Maintenance mode expired");
+        maintenance_ended(state, senders);
+    }
+}
+
+// Notifications were held back during maintenance. Catch up if the food ran
+// low or was refilled in the meantime.
+fn maintenance_ended(state: &mut State, senders: &Senders) {
+    info!("Maintenance mode ended");
+    if let Some(low) = state.hopper.as_ref().map(|hopper| hopper.is_low()) {
+        if low != state.low_food_notified {
+            send_low_food(state, low, senders);
+        }
+    }
+}
+
+fn end_enrollment(state: &mut State, senders: &Senders) {
+    state.enrolling_until = None;
+    send_enrollment(false, senders);
+}
+
+fn send_enrollment(enrolling: bool, senders: &Senders) {
+    if let Some(enrollment_tx) = &senders.enrollment {
+        if let Err(_err) = enrollment_tx.broadcast(enrolling) {
+            error!("Error sending enrollment {}", enrolling);
+        }
+    }
+}
+
 ///
 pub fn reducer_task(
     state_handle: Arc<Mutex<State>>,
     mut rx: EventRx,
-    mut action_tx: ActionTx,
+    mut senders: Senders,
 ) -> task::JoinHandle<()> {
     task::spawn(async move {
-        // rx.recv() returns None when all TXs are shutdown
-        while let Some(event) = rx.recv().await {
-            reducer(event, &state_handle, &action_tx).await
+        loop {
+            let enrolling_until = state_handle.lock().await.enrolling_until;
+            select! {
+                // rx.recv() returns None when all TXs are shutdown
+                event = rx.recv().fuse() => match event {
+                    Some(event) => reducer(event, &state_handle, &senders).await,
+                    None => break,
+                },
+                _ = wait_until(enrolling_until.map(time::Instant::from_std)).fuse() => {
+                    info!("Enrollment mode timed out");
+                    end_enrollment(&mut *state_handle.lock().await, &senders);
+                }
+            }
         }
         debug!("All Recievers dropped");
         // This will stall until all RX side have been shutdown
-        action_tx.closed().await;
+        senders.actions.closed().await;
         debug!("All recivers dropped. Quitting now");
     })
 }
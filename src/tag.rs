@@ -0,0 +1,87 @@
+use std::fmt;
+
+/// An id read from an RFID tag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TagId {
+    /// 125 kHz EM4100 card or fob. The version byte is often used as the
+    /// facility code.
+    Em4100 { version: u8, card: u32 },
+    /// 134.2 kHz FDX-B (ISO 11784/11785) pet microchip
+    FdxB { country: u16, national_id: u64 },
+    /// Id from a Wiegand reader. Wiegand-26 has 24 bits of facility and card
+    /// and Wiegand-34 has 32 bits. These are the low bits of an EM4100 id.
+    Wiegand { bits: u8, data: u32 },
+}
+
+impl TagId {
+    /// The decimal number printed on most cards. 10 digits for EM4100 tags and
+    /// the 15 digit country and national id for FDX-B chips.
+    pub fn decimal(&self) -> String {
+        match self {
+            TagId::Em4100 { card, .. } => format!("{:010}", card),
+            TagId::Wiegand { data, .. } => format!("{:010}", data),
+            TagId::FdxB {
+                country,
+                national_id,
+            } => format!("{:03}{:012}", country, national_id),
+        }
+    }
+
+    /// The Wiegand-26 style facility and card number, eg "131,31259".
+    /// FDX-B chips don't have one.
+    pub fn facility_card(&self) -> Option<String> {
+        match self {
+            TagId::Em4100 { card, .. } | TagId::Wiegand { data: card, .. } => {
+                let facility = (card >> 16) & 0xFF;
+                Some(format!("{:03},{:05}", facility, card & 0xFFFF))
+            }
+            TagId::FdxB { .. } => None,
+        }
+    }
+
+    /// The full id as hex digits
+    pub fn hex(&self) -> String {
+        match self {
+            TagId::Em4100 { version, card } => format!("{:02X}{:08X}", version, card),
+            TagId::Wiegand { data, .. } => format!("{:08X}", data),
+            TagId::FdxB {
+                country,
+                national_id,
+            } => format!("{:03X}{:010X}", country, national_id),
+        }
+    }
+
+    /// Check the tag against an id from the config. The id can be written in
+    /// any of the display formats.
+    pub fn matches(&self, id: &str) -> bool {
+        let id = id.trim();
+        if let Some(facility_card) = self.facility_card() {
+            if id.contains(',') {
+                return parse_facility_card(id) == parse_facility_card(&facility_card);
+            }
+        }
+
+        let same_number = match (id.parse::<u64>(), self.decimal().parse::<u64>()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        };
+        same_number || id.eq_ignore_ascii_case(&self.hex())
+    }
+}
+
+fn parse_facility_card(id: &str) -> Option<(u8, u16)> {
+    let mut parts = id.split(',').map(|part| part.trim());
+    let facility = parts.next()?.parse().ok()?;
+    let card = parts.next()?.parse().ok()?;
+    match parts.next() {
+        Some(_) => None,
+        None => Some((facility, card)),
+    }
+}
+
+impl fmt::Display for TagId {
+    /// Formats the tag as its decimal number
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.decimal())
+    }
+}
@@ -1,6 +1,7 @@
 use log::debug;
 use rppal::gpio::{InputPin, Level, Trigger};
 use tokio::task;
+use tokio::time::{delay_until, Instant};
 
 use crate::state::{Action, ActionRx};
 
@@ -29,3 +30,11 @@ where
         }
     })
 }
+
+/// Resolves at the deadline, or never if there isn't one
+pub async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => delay_until(deadline).await,
+        None => futures::future::pending().await,
+    }
+}